@@ -1,31 +1,209 @@
-use clap::Parser;
-use iroh_net::NodeId;
-use munin_proto::AudioSource;
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use clap::{builder::PossibleValue, CommandFactory, FromArgMatches, Parser};
+use clap_complete::Shell;
+use iroh_net::{relay::RelayUrl, ticket::NodeTicket, NodeAddr, NodeId};
+use munin_proto::{
+    AlarmSpec, AudioOutput, AudioSource, DelegationChain, Grant, PairingTicket, RequestKind, Role,
+    TimeOfDay, Weekdays,
+};
 
 #[derive(Debug, Clone, Parser)]
 pub struct Args {
+    /// Connect to this address instead of the known ones, e.g. 192.168.1.20:41234
+    ///
+    /// Only for commands with a single node. For add-node, the address is saved
+    /// with the alias.
+    #[clap(long, global = true, value_name = "IP:PORT")]
+    pub addr: Vec<SocketAddr>,
+    /// Don't look up addresses, only use the known ones and --addr
+    #[clap(long, global = true)]
+    pub no_discovery: bool,
     #[clap(subcommand)]
     pub subcommand: Subcommand,
 }
 
+impl Args {
+    /// The clap command, with `sounds` offered as values for `--source`
+    pub fn command_with_sounds(sounds: &BTreeSet<String>) -> clap::Command {
+        with_sound_parser(Self::command(), &SoundParser::new(sounds))
+    }
+
+    /// Parse the command line, with `sounds` offered as values for `--source`
+    pub fn parse_with_sounds(sounds: &BTreeSet<String>) -> Self {
+        let matches = Self::command_with_sounds(sounds).get_matches();
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+}
+
+/// Install the sound parser on every `source` argument of `cmd` and its subcommands
+fn with_sound_parser(mut cmd: clap::Command, parser: &SoundParser) -> clap::Command {
+    if cmd.get_arguments().any(|arg| arg.get_id() == "source") {
+        cmd = cmd.mut_arg("source", |arg| arg.value_parser(parser.clone()));
+    }
+    let names = cmd
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect::<Vec<_>>();
+    for name in names {
+        cmd = cmd.mut_subcommand(name, |sub| with_sound_parser(sub, parser));
+    }
+    cmd
+}
+
+/// Value parser for [AudioSource] that accepts any sound name, but offers the
+/// builtin sounds and the sounds we have seen on remote nodes for completion.
+#[derive(Debug, Clone)]
+struct SoundParser {
+    names: Vec<String>,
+}
+
+impl SoundParser {
+    fn new(sounds: &BTreeSet<String>) -> Self {
+        let mut names = munin_proto::BUILTIN_SOUNDS
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<BTreeSet<_>>();
+        names.extend(sounds.iter().cloned());
+        Self {
+            names: names.into_iter().collect(),
+        }
+    }
+}
+
+impl clap::builder::TypedValueParser for SoundParser {
+    type Value = AudioSource;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let value = value
+            .to_str()
+            .ok_or_else(|| clap::Error::new(clap::error::ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        value.parse().map_err(|e: String| {
+            clap::Error::raw(clap::error::ErrorKind::InvalidValue, e + "\n").with_cmd(cmd)
+        })
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            self.names
+                .iter()
+                .map(|name| PossibleValue::new(name.clone())),
+        ))
+    }
+}
+
 #[derive(Debug, Clone, Parser)]
 pub enum Subcommand {
     AddNode(AddNode),
     RemoveNode(RemoveNode),
     ListNodes(ListNodes),
+    /// Name aliases after the daemons, and save aliases for node ids
+    RefreshNodes(RefreshNodes),
+    /// Find daemons on the local network, and whether they allow us
+    ///
+    /// This only asks the daemons for their version, with --request-access the
+    /// daemons that don't know us yet also get an access request from us.
+    Discover(Discover),
+
+    /// Add ourselves to a daemon with a ticket from `munin-d pair`, and save an alias for it
+    Pair(Pair),
+    /// Sign a delegation for another controller, as owner or passing on our own delegation
+    Delegate(Delegate),
+    /// Store a delegation signed for us, it is presented to daemons from now on
+    UseDelegation(UseDelegation),
 
+    /// Show protocol version and capabilities of nodes
+    Version(Version),
     ListTasks(ListTasks),
     KillTask(KillTask),
     SystemInfo(SystemInfo),
+    /// Show connection counters and banned nodes of daemons
+    Stats(Stats),
+    /// Change the name of a daemon, needs the admin role
+    SetName(SetName),
     PlayAudio(PlayAudio),
+    StopAudio(StopAudio),
+    ListSounds(ListSounds),
+    ListAudioDevices(ListAudioDevices),
+
+    /// Manage alarms that the daemon plays on its own
+    #[clap(subcommand)]
+    Alarm(Alarm),
+
+    /// Manage the nodes that may control a daemon, needs the admin role
+    #[clap(subcommand)]
+    AllowedNodes(AllowedNodes),
+
+    /// Ask a daemon to allow us, an admin has to approve the request
+    RequestAccess(RequestAccess),
+
+    /// Manage access requests of unknown nodes, needs the admin role
+    #[clap(subcommand)]
+    AccessRequests(AccessRequests),
+
+    /// Show who did what on a daemon, needs the admin role
+    AuditLog(AuditLog),
     Shutdown(Shutdown),
+
+    /// Print a shell completion script
+    Completions(Completions),
 }
 
 #[derive(Debug, Clone, Parser)]
 pub struct AddNode {
+    /// Alias for the node, defaults to the name of the daemon
     #[clap(long)]
-    pub name: String,
-    pub addr: NodeId,
+    pub name: Option<String>,
+    /// Node id, or a ticket with the addresses of the node
+    pub node: NodeTicketOrId,
+    /// Relay the node uses, instead of the one in the ticket
+    #[clap(long)]
+    pub relay: Option<RelayUrl>,
+}
+
+impl AddNode {
+    /// The address to save for the alias, with extra direct addresses from `--addr`
+    pub fn node_addr(&self, direct_addrs: &[SocketAddr]) -> NodeAddr {
+        let mut addr = match &self.node {
+            NodeTicketOrId::Ticket(ticket) => ticket.node_addr().clone(),
+            NodeTicketOrId::Id(node_id) => NodeAddr::new(*node_id),
+        };
+        if let Some(relay) = &self.relay {
+            addr.info.relay_url = Some(relay.clone());
+        }
+        addr.with_direct_addresses(direct_addrs.iter().copied())
+    }
+}
+
+/// A node given by its id alone, or with its addresses in a ticket
+#[derive(Debug, Clone)]
+pub enum NodeTicketOrId {
+    Ticket(NodeTicket),
+    Id(NodeId),
+}
+
+impl FromStr for NodeTicketOrId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(node_id) = NodeId::from_str(s) {
+            return Ok(Self::Id(node_id));
+        }
+        NodeTicket::from_str(s)
+            .map(Self::Ticket)
+            .map_err(|_| format!("neither a node id nor a ticket: {}", s))
+    }
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -37,11 +215,81 @@ pub struct RemoveNode {
 #[derive(Debug, Clone, Parser)]
 pub struct ListNodes {}
 
+#[derive(Debug, Clone, Parser)]
+pub struct RefreshNodes {
+    /// Aliases or node ids, all aliases if empty
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Discover {
+    /// How long to wait for answers
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "3s")]
+    pub wait: Duration,
+    /// Save an alias for every daemon found, named like the daemon
+    ///
+    /// Aliases that already exist for a daemon get its current addresses.
+    #[clap(long)]
+    pub add: bool,
+    /// Ask the daemons that don't know us yet for access
+    #[clap(long)]
+    pub request_access: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Pair {
+    pub ticket: PairingTicket,
+    /// Alias for the daemon, defaults to its name
+    #[clap(long)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Delegate {
+    /// Node id of the controller
+    pub controller: NodeId,
+    /// viewer, operator or admin
+    #[clap(long, default_value = "viewer")]
+    pub role: Role,
+    /// How long the delegation is valid, e.g. 30d
+    #[clap(long = "for", value_parser = parse_duration_from_now, default_value = "30d", conflicts_with = "until")]
+    pub duration: Duration,
+    /// Valid until this UTC time instead
+    #[clap(long, value_parser = humantime::parse_rfc3339_weak)]
+    pub until: Option<SystemTime>,
+    /// Only allow these kinds of requests, e.g. play-audio,stop-audio
+    #[clap(long, value_delimiter = ',')]
+    pub only: Option<Vec<RequestKind>>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct UseDelegation {
+    /// Delegation from `munin delegate`
+    pub delegation: DelegationChain,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Version {
+    pub id: Vec<String>,
+}
+
 #[derive(Debug, Clone, Parser)]
 pub struct SystemInfo {
     pub id: Vec<String>,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct SetName {
+    pub id: String,
+    /// The new name, shown to controllers and in local discovery
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Stats {
+    pub id: Vec<String>,
+}
+
 #[derive(Debug, Clone, Parser)]
 pub struct ListTasks {
     pub id: Vec<String>,
@@ -63,4 +311,263 @@ pub struct PlayAudio {
     pub id: Vec<String>,
     #[clap(long)]
     pub source: AudioSource,
+    /// Amplification factor, the daemon default is used if not set
+    #[clap(long)]
+    pub volume: Option<f32>,
+    /// Wait until the sound has finished playing
+    #[clap(long)]
+    pub wait: bool,
+    /// Play on the output device with this name instead of the default device
+    #[clap(long, conflicts_with = "all_devices")]
+    pub device: Option<String>,
+    /// Play on all output devices
+    #[clap(long)]
+    pub all_devices: bool,
+}
+
+impl PlayAudio {
+    pub fn output(&self) -> AudioOutput {
+        output(&self.device, self.all_devices)
+    }
+}
+
+fn output(device: &Option<String>, all_devices: bool) -> AudioOutput {
+    if all_devices {
+        AudioOutput::All
+    } else if let Some(name) = device {
+        AudioOutput::Device(name.clone())
+    } else {
+        AudioOutput::Default
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+pub enum Alarm {
+    /// Add a recurring alarm
+    Add(AddAlarm),
+    List(ListAlarms),
+    Remove(RemoveAlarm),
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct AddAlarm {
+    pub id: Vec<String>,
+    /// Local time of the daemon, as HH:MM
+    #[clap(long)]
+    pub at: TimeOfDay,
+    /// Only monday to friday
+    #[clap(long, conflicts_with_all = ["weekend", "days"])]
+    pub weekdays: bool,
+    /// Only saturday and sunday
+    #[clap(long, conflicts_with = "days")]
+    pub weekend: bool,
+    /// Comma separated days, e.g. mon,wed,fri. Every day if not set
+    #[clap(long)]
+    pub days: Option<Weekdays>,
+    #[clap(long)]
+    pub source: AudioSource,
+    /// Amplification factor, the daemon default is used if not set
+    #[clap(long)]
+    pub volume: Option<f32>,
+    /// Play on the output device with this name instead of the default device
+    #[clap(long, conflicts_with = "all_devices")]
+    pub device: Option<String>,
+    /// Play on all output devices
+    #[clap(long)]
+    pub all_devices: bool,
+}
+
+impl AddAlarm {
+    pub fn spec(&self) -> AlarmSpec {
+        let days = if self.weekdays {
+            Weekdays::WEEKDAYS
+        } else if self.weekend {
+            Weekdays::WEEKEND
+        } else {
+            self.days.unwrap_or(Weekdays::EVERY_DAY)
+        };
+        AlarmSpec {
+            at: self.at,
+            days,
+            source: self.source.clone(),
+            volume: self.volume,
+            output: output(&self.device, self.all_devices),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListAlarms {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RemoveAlarm {
+    pub id: String,
+    /// The alarm id, as shown by `alarm list`
+    pub alarm: u32,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub enum AllowedNodes {
+    List(ListAllowedNodes),
+    /// Allow a node to connect, or replace its grant
+    #[clap(visible_alias = "grant")]
+    Add(AllowNode),
+    #[clap(visible_alias = "revoke")]
+    Remove(DisallowNode),
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListAllowedNodes {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct AllowNode {
+    pub id: String,
+    /// Node id or alias of the node to allow
+    pub node: String,
+    /// viewer, operator or admin
+    #[clap(long, default_value = "viewer")]
+    pub role: Role,
+    /// Remove the node again after this long, e.g. 2d
+    #[clap(long = "for", value_parser = parse_duration_from_now, conflicts_with = "until")]
+    pub duration: Option<Duration>,
+    /// Remove the node again at this UTC time
+    #[clap(long, value_parser = humantime::parse_rfc3339_weak)]
+    pub until: Option<SystemTime>,
+    /// Only allow these kinds of requests, e.g. play-audio,stop-audio
+    #[clap(long, value_delimiter = ',')]
+    pub only: Option<Vec<RequestKind>>,
+}
+
+impl AllowNode {
+    pub fn grant(&self) -> anyhow::Result<Grant> {
+        let expires = match (self.until, self.duration) {
+            (Some(until), _) => Some(until),
+            (None, Some(duration)) => Some(
+                SystemTime::now()
+                    .checked_add(duration)
+                    .context("--for is too long")?,
+            ),
+            (None, None) => None,
+        };
+        Ok(Grant {
+            role: self.role,
+            expires,
+            requests: self
+                .only
+                .as_ref()
+                .map(|only| only.iter().copied().collect()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct DisallowNode {
+    pub id: String,
+    /// Node id or alias of the node to remove
+    pub node: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RequestAccess {
+    pub id: Vec<String>,
+    /// Name to show to the admins of the daemon
+    #[clap(long)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub enum AccessRequests {
+    List(ListAccessRequests),
+    /// Allow a node that has requested access
+    Approve(ApproveAccess),
+    /// Deny a node that has requested access
+    Deny(DenyAccess),
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListAccessRequests {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ApproveAccess {
+    pub id: String,
+    /// Node id of the requesting node
+    pub node: String,
+    /// viewer, operator or admin
+    #[clap(long, default_value = "viewer")]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct DenyAccess {
+    pub id: String,
+    /// Node id of the requesting node
+    pub node: String,
+}
+
+/// The latest time that can be shown as a timestamp, 9999-12-31T23:59:59Z
+const MAX_TIME_SECS: u64 = 253_402_300_799;
+
+/// Parse a duration that still gives a valid time when added to the current time
+fn parse_duration_from_now(s: &str) -> Result<Duration, String> {
+    let duration = humantime::parse_duration(s).map_err(|e| e.to_string())?;
+    let max = UNIX_EPOCH + Duration::from_secs(MAX_TIME_SECS);
+    match SystemTime::now().checked_add(duration) {
+        Some(time) if time <= max => Ok(duration),
+        _ => Err(format!("{} is too far in the future", s)),
+    }
+}
+
+/// Parse a time as either a duration ago, e.g. "2h", or a timestamp, e.g. "2024-10-01 12:00:00"
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(s) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .filter(|time| *time >= UNIX_EPOCH)
+            .ok_or_else(|| format!("{} ago is before 1970", s));
+    }
+    humantime::parse_rfc3339_weak(s)
+        .map_err(|_| format!("expected a duration like 2h or a UTC timestamp, got {}", s))
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct AuditLog {
+    pub id: Vec<String>,
+    /// Only entries after this time, a duration ago (e.g. 2h) or a UTC timestamp
+    #[clap(long, value_parser = parse_time)]
+    pub since: Option<SystemTime>,
+    /// Only entries before this time, a duration ago (e.g. 2h) or a UTC timestamp
+    #[clap(long, value_parser = parse_time)]
+    pub until: Option<SystemTime>,
+    /// Only entries for this node id or alias
+    #[clap(long)]
+    pub node: Option<String>,
+    /// Show at most this many entries, the most recent ones
+    #[clap(long)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListAudioDevices {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct StopAudio {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ListSounds {
+    pub id: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Completions {
+    pub shell: Shell,
 }
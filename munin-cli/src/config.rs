@@ -1,21 +1,110 @@
-use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use anyhow::{anyhow, Result};
-use iroh_net::NodeId;
+use anyhow::{anyhow, Context, Result};
+use iroh_net::{NodeAddr, NodeId};
+use munin_proto::{DelegationChain, Migration, RelayConfig};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub secret_key: iroh_net::key::SecretKey,
-    pub nodes: BTreeMap<String, NodeId>,
+    /// Aliases, with the addresses we know for them
+    pub nodes: BTreeMap<String, NodeAddr>,
+    /// Sound names seen in the catalogs of remote nodes, offered for completion
+    pub sounds: BTreeSet<String>,
+    /// Delegation from the owner of our daemons, presented with every request
+    pub delegation: Option<DelegationChain>,
+    pub relay: RelayConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TomlConfig {
+    /// See [MIGRATIONS]
+    #[serde(default)]
+    version: u32,
     secret_key: String,
-    nodes: BTreeMap<String, String>,
+    #[serde(default)]
+    nodes: BTreeMap<String, TomlNode>,
+    #[serde(default)]
+    sounds: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delegation: Option<String>,
+    #[serde(default, skip_serializing_if = "RelayConfig::is_default")]
+    relay: RelayConfig,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TomlNode {
+    node_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relay_url: Option<String>,
+    /// Direct socket addresses, tried before discovery
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    addrs: Vec<String>,
+}
+
+impl TryFrom<TomlNode> for NodeAddr {
+    type Error = anyhow::Error;
+
+    fn try_from(value: TomlNode) -> Result<Self> {
+        let node_id = NodeId::from_str(&value.node_id)?;
+        let relay_url = value
+            .relay_url
+            .map(|url| url.parse())
+            .transpose()
+            .context("invalid relay url")?;
+        let addrs = value
+            .addrs
+            .iter()
+            .map(|addr| addr.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .context("invalid socket address")?;
+        Ok(NodeAddr::from_parts(node_id, relay_url, addrs))
+    }
+}
+
+impl From<NodeAddr> for TomlNode {
+    fn from(value: NodeAddr) -> Self {
+        TomlNode {
+            node_id: value.node_id.to_string(),
+            relay_url: value.info.relay_url.map(|url| url.to_string()),
+            addrs: value
+                .info
+                .direct_addresses
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Upgrades of the config file, the version of the file is the number of
+/// migrations applied to it
+///
+/// New fields with a serde default don't need a migration, only changes to
+/// existing fields do.
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: only added the version
+    |_| Ok(()),
+    // 1 -> 2: aliases were plain node ids, now they are tables with addresses
+    |config| {
+        if let Some(toml::Value::Table(nodes)) = config.get_mut("nodes") {
+            for (_, node) in nodes.iter_mut() {
+                if let toml::Value::String(node_id) = node {
+                    let mut table = toml::Table::new();
+                    table.insert("node_id".to_string(), node_id.clone().into());
+                    *node = table.into();
+                }
+            }
+        }
+        Ok(())
+    },
+];
+
 impl TryFrom<TomlConfig> for Config {
     type Error = anyhow::Error;
 
@@ -24,21 +113,35 @@ impl TryFrom<TomlConfig> for Config {
         let nodes = value
             .nodes
             .into_iter()
-            .map(|(name, id)| anyhow::Ok((name, NodeId::from_str(&id)?)))
+            .map(|(name, node)| anyhow::Ok((name, node.try_into()?)))
             .collect::<Result<_, _>>()?;
-        Ok(Config { secret_key, nodes })
+        let delegation = value
+            .delegation
+            .map(|delegation| DelegationChain::from_str(&delegation))
+            .transpose()?;
+        Ok(Config {
+            secret_key,
+            nodes,
+            sounds: value.sounds,
+            delegation,
+            relay: value.relay,
+        })
     }
 }
 
 impl From<Config> for TomlConfig {
     fn from(value: Config) -> Self {
         TomlConfig {
+            version: MIGRATIONS.len() as u32,
             secret_key: value.secret_key.to_string(),
             nodes: value
                 .nodes
                 .into_iter()
-                .map(|(name, id)| (name, id.to_string()))
+                .map(|(name, addr)| (name, addr.into()))
                 .collect(),
+            sounds: value.sounds,
+            delegation: value.delegation.map(|delegation| delegation.to_string()),
+            relay: value.relay,
         }
     }
 }
@@ -68,25 +171,49 @@ impl Config {
         let path = dir.join("config.toml");
         let data = toml::to_string_pretty(&TomlConfig::from(self.clone()))?;
         tracing::info!("Saving config to {}", path.display());
-        std::fs::write(&path, data)?;
+        // replace the file in one step, so the secret key is never half written
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &path)?;
         Ok(())
     }
 
+    /// Load the config, upgrading it if it is from an older version
+    ///
+    /// The old file is kept as a backup. If anything goes wrong, the file is
+    /// left alone.
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        tracing::info!("Loading config from {}", path.display());
+        let data = std::fs::read_to_string(path)?;
+        let (table, migrated) = munin_proto::upgrade_config(&data, MIGRATIONS)?;
+        let config: TomlConfig = toml::Value::Table(table).try_into()?;
+        let config = Self::try_from(config)?;
+        if let Some(version) = migrated {
+            let backup = munin_proto::backup_config(path, version)?;
+            tracing::info!(
+                "Upgraded config from version {}, the old one is at {}",
+                version,
+                backup.display()
+            );
+            config.save()?;
+        }
+        Ok(config)
+    }
+
     pub fn get_or_create() -> anyhow::Result<Self> {
         let dir = munin_data_root()?;
         std::fs::create_dir_all(&dir)?;
         let path = dir.join("config.toml");
         if path.exists() {
-            tracing::info!("Loading config from {}", path.display());
-            let data = std::fs::read_to_string(&path)?;
-            let config: TomlConfig = toml::from_str(&data)?;
-            let config = Self::try_from(config)?;
-            Ok(config)
+            Self::load(&path)
         } else {
             tracing::info!("Creating new config at {}", path.display());
             let config = Self {
                 secret_key: iroh_net::key::SecretKey::generate(),
                 nodes: BTreeMap::new(),
+                sounds: BTreeSet::new(),
+                delegation: None,
+                relay: RelayConfig::Default,
             };
             let data = toml::to_string_pretty(&TomlConfig::from(config.clone()))?;
             std::fs::write(&path, data)?;
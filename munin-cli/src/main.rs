@@ -1,42 +1,191 @@
-use std::str::FromStr;
+use std::{
+    collections::BTreeMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
+    time::SystemTime,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use args::Subcommand;
-use clap::Parser;
 use config::Config;
-use iroh_net::{ticket::NodeTicket, NodeAddr, NodeId};
+use iroh_net::{
+    discovery::{dns::DnsDiscovery, ConcurrentDiscovery},
+    ticket::NodeTicket,
+    NodeAddr, NodeId,
+};
+use munin_proto::{Delegation, DelegationChain, LocalDiscovery, Request, Response};
+use serde::de::DeserializeOwned;
 
 mod args;
 mod config;
 
 /// Given a list of ids from the cli, return a list of node ids and their names
 ///
-/// If the list is empty, return all nodes in the config
-fn get_nodes(ids: Vec<String>, config: &config::Config) -> Result<Vec<(String, NodeAddr)>> {
-    if ids.is_empty() {
+/// If the list is empty, return all nodes in the config. `addrs` from `--addr`
+/// replace the known addresses, so they need exactly one node.
+fn get_nodes(
+    ids: Vec<String>,
+    config: &config::Config,
+    addrs: &[SocketAddr],
+) -> Result<Vec<(String, NodeAddr)>> {
+    let mut nodes = if ids.is_empty() {
         config
             .nodes
             .iter()
-            .map(|(name, id)| Ok((name.clone(), (*id).into())))
+            .map(|(name, addr)| (name.clone(), addr.clone()))
             .collect()
     } else {
+        // nodes given by node id are labeled with their alias, if they have one
+        let label = |node_id: NodeId| match config
+            .nodes
+            .iter()
+            .find(|(_, addr)| addr.node_id == node_id)
+        {
+            Some((alias, _)) => format!("{} ({})", alias, node_id.fmt_short()),
+            None => node_id.to_string(),
+        };
         ids.into_iter()
             .map(|id| {
                 if let Ok(nodeid) = NodeId::from_str(&id) {
-                    Ok((nodeid.to_string(), NodeAddr::from(nodeid)))
+                    Ok((label(nodeid), NodeAddr::from(nodeid)))
                 } else if let Ok(ticket) = NodeTicket::from_str(&id) {
                     Ok((
-                        ticket.node_addr().node_id.to_string(),
+                        label(ticket.node_addr().node_id),
                         ticket.node_addr().clone(),
                     ))
-                } else if let Some(nodeid) = config.nodes.get(&id) {
-                    Ok((id, (*nodeid).into()))
+                } else if let Some(addr) = config.nodes.get(&id) {
+                    Ok((id, addr.clone()))
                 } else {
                     Err(anyhow::anyhow!("Neither node id nor valid alias: {}", id))
                 }
             })
-            .collect()
+            .collect::<Result<Vec<_>>>()?
+    };
+    if !addrs.is_empty() {
+        anyhow::ensure!(
+            nodes.len() == 1,
+            "--addr needs exactly one node, but {} were given",
+            nodes.len()
+        );
+        nodes[0].1.info.direct_addresses = addrs.iter().copied().collect();
+    }
+    Ok(nodes)
+}
+
+/// Resolve a node id or alias from the cli to a node id
+fn get_node_id(id: &str, config: &config::Config) -> Result<NodeId> {
+    let (_, addr) = get_nodes(vec![id.to_string()], config, &[])?
+        .pop()
+        .expect("one id gives one node");
+    Ok(addr.node_id)
+}
+
+/// An endpoint to talk to daemons, and the delegation to present to them
+struct Client {
+    endpoint: iroh_net::Endpoint,
+    delegation: Option<DelegationChain>,
+}
+
+/// A local port that stays the same between runs
+///
+/// Daemons keep sending to the address of our previous run for a while. Without
+/// a relay to fall back to, connecting from a new port fails until then. If the
+/// port is taken, e.g. by another run, a random one is used.
+fn stable_port(node_id: NodeId) -> u16 {
+    let bytes = node_id.as_bytes();
+    49152 + u16::from_le_bytes([bytes[0], bytes[1]]) % 16384
+}
+
+/// Send a single request to a node and read the response
+///
+/// The outer result is for connection errors, the inner one for errors reported by the node.
+async fn call<T: DeserializeOwned>(
+    client: &Client,
+    addr: NodeAddr,
+    request: &Request,
+) -> Result<Response<T>> {
+    let connection = client.endpoint.connect(addr, munin_proto::ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let request = match &client.delegation {
+        Some(chain) => postcard::to_allocvec(&Request::Delegated {
+            chain: chain.clone(),
+            request: Box::new(request.clone()),
+        })?,
+        None => postcard::to_allocvec(request)?,
+    };
+    send.write_all(&request).await?;
+    send.finish()?;
+    let msg = recv.read_to_end(munin_proto::MAX_RESPONSE_SIZE).await?;
+    let msg = postcard::from_bytes::<Response<T>>(&msg)?;
+    connection.close(0u32.into(), b"OK");
+    // not being allowed at all is not specific to the request, so it is a connection error
+    if let Err(
+        e @ (munin_proto::Error::PendingApproval
+        | munin_proto::Error::AccessDenied
+        | munin_proto::Error::NotAllowed),
+    ) = msg
+    {
+        return Err(e.into());
     }
+    Ok(msg)
+}
+
+/// Ask a node for its protocol version and capabilities
+async fn get_version(client: &Client, addr: NodeAddr) -> Result<munin_proto::VersionResponse> {
+    Ok(call(client, addr, &Request::GetVersion).await??)
+}
+
+/// The other node an alias is already used for, if any
+fn alias_owner(config: &Config, alias: &str, node_id: NodeId) -> Option<NodeId> {
+    config
+        .nodes
+        .get(alias)
+        .map(|known| known.node_id)
+        .filter(|known| *known != node_id)
+}
+
+/// Whether a node allows us, asking it for access if `request_access` is set
+async fn access_status(client: &Client, addr: NodeAddr, request_access: bool) -> String {
+    // unlike other requests, asking for the version doesn't file an access request
+    let mut res = get_version(client, addr.clone()).await.map(|_| ());
+    let not_allowed = matches!(
+        res.as_ref().err().and_then(|e| e.downcast_ref()),
+        Some(munin_proto::Error::NotAllowed)
+    );
+    if request_access && not_allowed {
+        let request = Request::RequestAccess { name: None };
+        res = call::<munin_proto::Role>(client, addr, &request)
+            .await
+            .and_then(|res| Ok(res.map(|_| ())?));
+    }
+    match res {
+        Ok(()) => "allowed".to_string(),
+        Err(e) => match e.downcast_ref::<munin_proto::Error>() {
+            Some(munin_proto::Error::NotAllowed) => "not allowed".to_string(),
+            Some(munin_proto::Error::PendingApproval) => {
+                "not allowed, access requested".to_string()
+            }
+            Some(munin_proto::Error::AccessDenied) => "denied".to_string(),
+            Some(e) => format!("error: {}", e),
+            None => format!("unreachable: {}", e),
+        },
+    }
+}
+
+/// The role of a grant, followed by its limits if there are any
+fn format_grant(grant: &munin_proto::Grant) -> String {
+    let mut text = grant.role.to_string();
+    if let Some(expires) = grant.expires {
+        text.push_str(&format!(
+            " until {}",
+            humantime::format_rfc3339_seconds(expires)
+        ));
+    }
+    if let Some(requests) = &grant.requests {
+        let requests = requests.iter().map(|kind| kind.name()).collect::<Vec<_>>();
+        text.push_str(&format!(" only {}", requests.join(",")));
+    }
+    text
 }
 
 #[tokio::main]
@@ -45,111 +194,661 @@ async fn main() -> anyhow::Result<()> {
     let config = config::Config::get_or_create()?;
     let pubkey = config.secret_key.public();
     let res = main_impl(config).await;
-    if let Err(err) = &res {
-        let text = format!("{:?}", err);
-        if text.contains("unauthorized node") {
-            println!("The remote node rejected the connection");
-            println!("You need to add {pubkey} to the list of allowed nodes");
+    match res
+        .as_ref()
+        .err()
+        .and_then(|err| err.downcast_ref::<munin_proto::Error>())
+    {
+        Some(munin_proto::Error::PendingApproval) => {
+            println!("The remote node does not know us yet, our access request is pending");
+            println!("An admin needs to approve {pubkey}, or add it to the list of allowed nodes");
+            std::process::exit(1);
+        }
+        Some(munin_proto::Error::AccessDenied) => {
+            println!("The remote node rejected the connection, an admin has denied {pubkey}");
             std::process::exit(1);
         }
+        Some(munin_proto::Error::NotAllowed) => {
+            println!("The remote node does not know us, use request-access to ask for access");
+            println!("Or an admin can add {pubkey} to the list of allowed nodes");
+            std::process::exit(1);
+        }
+        _ => res,
     }
-    res
 }
 
 async fn main_impl(mut config: Config) -> anyhow::Result<()> {
+    let args = args::Args::parse_with_sounds(&config.sounds);
+    if let Subcommand::Completions(completions) = &args.subcommand {
+        let mut cmd = args::Args::command_with_sounds(&config.sounds);
+        clap_complete::generate(completions.shell, &mut cmd, "munin", &mut std::io::stdout());
+        return Ok(());
+    }
     println!("I am {}", config.secret_key.public());
-    let args = args::Args::parse();
-    let create_endpoint = || {
-        iroh_net::Endpoint::builder()
-            .discovery(Box::new(iroh_net::discovery::dns::DnsDiscovery::n0_dns()))
+    let create_client = || {
+        let port = stable_port(config.secret_key.public());
+        let mut builder = iroh_net::Endpoint::builder()
             .secret_key(config.secret_key.clone())
-            .bind()
+            .relay_mode(config.relay.mode())
+            .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))
+            .bind_addr_v6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0));
+        if !args.no_discovery {
+            builder = builder.discovery(Box::new(ConcurrentDiscovery::from_services(vec![
+                Box::new(DnsDiscovery::n0_dns()),
+                Box::new(LocalDiscovery::resolve_only()),
+            ])));
+        }
+        let delegation = config.delegation.clone();
+        async move {
+            let endpoint = builder.bind().await?;
+            anyhow::Ok(Client {
+                endpoint,
+                delegation,
+            })
+        }
     };
     match args.subcommand {
+        Subcommand::Version(version) => {
+            let nodes = get_nodes(version.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (label, id) in nodes {
+                let version = match get_version(&client, id).await {
+                    Ok(version) => version,
+                    Err(e) => {
+                        println!("{}: {}", label, e);
+                        continue;
+                    }
+                };
+                // labeled like discover does, with the name the daemon gives itself
+                println!(
+                    "\"{}\" {}: protocol version {}",
+                    version.name, label, version.protocol_version
+                );
+                for capability in version.capabilities {
+                    println!("  {:?}", capability);
+                }
+            }
+        }
         Subcommand::ListTasks(list_tasks) => {
-            let nodes = get_nodes(list_tasks.id, &config)?;
-            let endpoint = create_endpoint().await?;
+            let nodes = get_nodes(list_tasks.id, &config, &args.addr)?;
+            let client = create_client().await?;
             for (name, id) in nodes {
                 println!("Listing tasks for {}", name);
-                let connection = endpoint.connect(id, munin_proto::ALPN).await?;
-                let (mut send, mut recv) = connection.open_bi().await?;
-                let request = munin_proto::Request::ListProcesses;
-                let request = postcard::to_allocvec(&request)?;
-                send.write_all(&request).await?;
-                send.finish()?;
-                let msg = recv.read_to_end(munin_proto::MAX_RESPONSE_SIZE).await?;
-                let msg = postcard::from_bytes::<munin_proto::ListProcessesResponse>(&msg)?;
-                for (pid, name) in msg.tasks {
-                    println!("{}: {}", pid, name);
+                let request = Request::ListProcesses;
+                let msg = call::<munin_proto::ListProcessesResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for (pid, name) in response.tasks {
+                            println!("{}: {}", pid, name);
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
                 }
-                connection.close(0u32.into(), b"OK");
             }
         }
         Subcommand::KillTask(kill_task) => {
-            let node = if let Ok(nodeid) = NodeId::from_str(&kill_task.id) {
-                nodeid
-            } else if let Some(nodeid) = config.nodes.get(&kill_task.id) {
-                *nodeid
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Neither node id nor valid alias: {}",
-                    kill_task.id
-                ));
-            };
-            let endpoint = create_endpoint().await?;
-            let connection = endpoint.connect(node.into(), munin_proto::ALPN).await?;
-            let (mut send, mut recv) = connection.open_bi().await?;
-            let request = munin_proto::Request::KillProcess(kill_task.pid);
-            let request = postcard::to_allocvec(&request)?;
-            send.write_all(&request).await?;
-            send.finish()?;
-            let msg = recv.read_to_end(munin_proto::MAX_RESPONSE_SIZE).await?;
-            let msg = postcard::from_bytes::<String>(&msg)?;
-            println!("{}", msg);
-            connection.close(0u32.into(), b"OK");
+            let (_, node) = get_nodes(vec![kill_task.id], &config, &args.addr)?
+                .pop()
+                .expect("one id gives one node");
+            let client = create_client().await?;
+            let request = Request::KillProcess(kill_task.pid);
+            let msg = call::<()>(&client, node, &request).await?;
+            match msg {
+                Ok(()) => println!("OK"),
+                Err(e) => println!("Error: {}", e),
+            }
         }
         Subcommand::SystemInfo(system_info) => {
-            let nodes = get_nodes(system_info.id, &config)?;
-            let endpoint = create_endpoint().await?;
-            for (name, id) in nodes {
-                println!("Getting system info for {}", name);
-                let connection = endpoint.connect(id, munin_proto::ALPN).await?;
-                let (mut send, mut recv) = connection.open_bi().await?;
-                let request = munin_proto::Request::GetSystemInfo;
-                let request = postcard::to_allocvec(&request)?;
-                send.write_all(&request).await?;
-                send.finish()?;
-                let msg = recv.read_to_end(munin_proto::MAX_RESPONSE_SIZE).await?;
-                let msg = postcard::from_bytes::<munin_proto::SysInfoResponse>(&msg)?;
-                println!("Hostname: {}", msg.hostname);
-                println!("Uptime: {:?}", msg.uptime);
-                connection.close(0u32.into(), b"OK");
+            let nodes = get_nodes(system_info.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (label, id) in nodes {
+                let request = Request::GetSystemInfo;
+                match call::<munin_proto::SysInfoResponse>(&client, id, &request).await {
+                    Ok(Ok(response)) => {
+                        println!("System info for \"{}\" {}", response.name, label);
+                        println!("Hostname: {}", response.hostname);
+                        println!("Uptime: {:?}", response.uptime);
+                    }
+                    Ok(Err(e)) => println!("{}: Error: {}", label, e),
+                    Err(e) => println!("{}: {}", label, e),
+                }
+            }
+        }
+        Subcommand::Stats(stats) => {
+            let nodes = get_nodes(stats.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Getting stats for {}", name);
+                let request = Request::GetStats;
+                let msg = call::<munin_proto::StatsResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        let seconds = |d: std::time::Duration| {
+                            humantime::format_duration(std::time::Duration::from_secs(d.as_secs()))
+                        };
+                        let connections = response.connections;
+                        println!("Uptime: {}", seconds(response.uptime));
+                        println!("Active connections: {}", connections.active);
+                        println!("Accepted: {}", connections.accepted);
+                        println!("Refused: {}", connections.refused);
+                        println!("Refused per node: {}", connections.refused_node);
+                        println!("Refused while banned: {}", connections.refused_banned);
+                        println!("Bans: {}", connections.bans);
+                        for (node, banned) in connections.banned {
+                            println!(
+                                "  {} banned for {} ({} bans)",
+                                node,
+                                seconds(banned.remaining),
+                                banned.bans
+                            );
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
             }
         }
         Subcommand::PlayAudio(play_audio) => {
-            let nodes = get_nodes(play_audio.id, &config)?;
-            let endpoint = create_endpoint().await?;
+            let output = play_audio.output();
+            let nodes = get_nodes(play_audio.id, &config, &args.addr)?;
+            let client = create_client().await?;
             for (name, id) in nodes {
                 println!("Playing audio on {}", name);
-                let connection = endpoint.connect(id, munin_proto::ALPN).await?;
-                let (mut send, mut recv) = connection.open_bi().await?;
-                let request = munin_proto::Request::PlayAudio(play_audio.source.clone());
-                let request = postcard::to_allocvec(&request)?;
-                send.write_all(&request).await?;
-                send.finish()?;
-                let msg = recv.read_to_end(munin_proto::MAX_RESPONSE_SIZE).await?;
-                let msg = postcard::from_bytes::<String>(&msg)?;
-                println!("{}", msg);
-                connection.close(0u32.into(), b"OK");
+                let request = Request::PlayAudio(munin_proto::PlayAudioRequest {
+                    source: play_audio.source.clone(),
+                    volume: play_audio.volume,
+                    wait: play_audio.wait,
+                    output: output.clone(),
+                });
+                let msg = call::<Vec<munin_proto::DevicePlayback>>(&client, id, &request).await?;
+                match msg {
+                    Ok(results) => {
+                        for playback in results {
+                            match playback.result {
+                                Ok(status) => println!("{}: {:?}", playback.device, status),
+                                Err(e) => println!("{}: Error: {}", playback.device, e),
+                            }
+                        }
+                    }
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::StopAudio(stop_audio) => {
+            let nodes = get_nodes(stop_audio.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Stopping audio on {}", name);
+                let request = Request::StopAudio;
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::ListAudioDevices(list_devices) => {
+            let nodes = get_nodes(list_devices.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Listing audio devices for {}", name);
+                let request = Request::ListAudioDevices;
+                let msg =
+                    call::<munin_proto::ListAudioDevicesResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for device in response.devices {
+                            if device.default {
+                                println!("{} (default)", device.name);
+                            } else {
+                                println!("{}", device.name);
+                            }
+                        }
+                    }
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::ListSounds(list_sounds) => {
+            let nodes = get_nodes(list_sounds.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            let mut seen = false;
+            for (name, id) in nodes {
+                println!("Listing sounds for {}", name);
+                let request = Request::ListSounds;
+                let msg = call::<munin_proto::ListSoundsResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for sound in response.sounds {
+                            if sound.builtin {
+                                println!("{}", sound.name);
+                            } else {
+                                println!("{} (user)", sound.name);
+                            }
+                            seen |= config.sounds.insert(sound.name);
+                        }
+                    }
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            if seen {
+                config.save()?;
+            }
+        }
+        Subcommand::Alarm(args::Alarm::Add(add_alarm)) => {
+            let spec = add_alarm.spec();
+            let nodes = get_nodes(add_alarm.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Adding alarm {} on {}", spec, name);
+                let request = Request::AddAlarm(spec.clone());
+                let msg = call::<u32>(&client, id, &request).await?;
+                match msg {
+                    Ok(alarm) => println!("Added alarm {}", alarm),
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::Alarm(args::Alarm::List(list_alarms)) => {
+            let nodes = get_nodes(list_alarms.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Listing alarms for {}", name);
+                let request = Request::ListAlarms;
+                let msg = call::<munin_proto::ListAlarmsResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for alarm in response.alarms {
+                            println!("{}: {}", alarm.id, alarm.spec);
+                        }
+                    }
+                    Err(munin_proto::Error::Unsupported(_)) => {
+                        println!("{} does not support audio", name)
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::Alarm(args::Alarm::Remove(remove_alarm)) => {
+            let nodes = get_nodes(vec![remove_alarm.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Removing alarm {} from {}", remove_alarm.alarm, name);
+                let request = Request::RemoveAlarm(remove_alarm.alarm);
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AllowedNodes(args::AllowedNodes::List(list)) => {
+            let nodes = get_nodes(list.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Listing allowed nodes for {}", name);
+                let request = Request::ListAllowedNodes;
+                let msg =
+                    call::<munin_proto::ListAllowedNodesResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for (node, grant) in response.nodes {
+                            let alias = config
+                                .nodes
+                                .iter()
+                                .find(|(_, addr)| addr.node_id == node)
+                                .map(|(alias, _)| format!(" ({})", alias))
+                                .unwrap_or_default();
+                            println!("{}{}: {}", node, alias, format_grant(&grant));
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AllowedNodes(args::AllowedNodes::Add(allow)) => {
+            let node = get_node_id(&allow.node, &config)?;
+            let grant = allow.grant()?;
+            let nodes = get_nodes(vec![allow.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Allowing {} as {} on {}", node, format_grant(&grant), name);
+                let request = Request::AllowNode {
+                    node,
+                    grant: grant.clone(),
+                };
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AllowedNodes(args::AllowedNodes::Remove(disallow)) => {
+            let node = get_node_id(&disallow.node, &config)?;
+            let nodes = get_nodes(vec![disallow.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Disallowing {} on {}", node, name);
+                let request = Request::DisallowNode(node);
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::RequestAccess(request_access) => {
+            let nodes = get_nodes(request_access.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Requesting access to {}", name);
+                let request = Request::RequestAccess {
+                    name: request_access.name.clone(),
+                };
+                match call::<munin_proto::Role>(&client, id, &request).await {
+                    Ok(Ok(role)) => println!("Already allowed as {}", role),
+                    Ok(Err(e)) => println!("Error: {}", e),
+                    Err(e) => match e.downcast_ref::<munin_proto::Error>() {
+                        Some(munin_proto::Error::PendingApproval) => {
+                            println!("Waiting for approval by an admin")
+                        }
+                        Some(munin_proto::Error::AccessDenied) => println!("Access was denied"),
+                        _ => return Err(e),
+                    },
+                }
+            }
+        }
+        Subcommand::AccessRequests(args::AccessRequests::List(list)) => {
+            let nodes = get_nodes(list.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Access requests for {}", name);
+                let request = Request::ListAccessRequests;
+                let msg =
+                    call::<munin_proto::ListAccessRequestsResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for access in response.requests {
+                            let name = access
+                                .name
+                                .map(|name| format!(" \"{}\"", name))
+                                .unwrap_or_default();
+                            let status = if access.denied { "denied" } else { "pending" };
+                            println!(
+                                "{}{}: {}, {} attempts, last {}",
+                                access.node,
+                                name,
+                                status,
+                                access.attempts,
+                                humantime::format_rfc3339_seconds(access.last_seen)
+                            );
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AccessRequests(args::AccessRequests::Approve(approve)) => {
+            let node = get_node_id(&approve.node, &config)?;
+            let nodes = get_nodes(vec![approve.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Approving {} as {} on {}", node, approve.role, name);
+                let request = Request::ApproveAccess {
+                    node,
+                    role: approve.role,
+                };
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AccessRequests(args::AccessRequests::Deny(deny)) => {
+            let node = get_node_id(&deny.node, &config)?;
+            let nodes = get_nodes(vec![deny.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Denying {} on {}", node, name);
+                let request = Request::DenyAccess(node);
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
+        Subcommand::AuditLog(audit_log) => {
+            let query = munin_proto::AuditQuery {
+                since: audit_log.since,
+                until: audit_log.until,
+                node: audit_log
+                    .node
+                    .map(|node| get_node_id(&node, &config))
+                    .transpose()?,
+                limit: audit_log.limit,
+            };
+            let nodes = get_nodes(audit_log.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Audit log of {}", name);
+                let request = Request::GetAuditLog(query.clone());
+                let msg = call::<munin_proto::AuditLogResponse>(&client, id, &request).await?;
+                match msg {
+                    Ok(response) => {
+                        for entry in response.entries {
+                            let outcome = match entry.outcome {
+                                munin_proto::AuditOutcome::Ok => "OK".to_string(),
+                                munin_proto::AuditOutcome::Error(e) => format!("Error: {}", e),
+                                munin_proto::AuditOutcome::Unauthorized => {
+                                    "unauthorized".to_string()
+                                }
+                            };
+                            println!(
+                                "{} {} {}: {}",
+                                humantime::format_rfc3339_seconds(entry.time),
+                                entry.node.fmt_short(),
+                                entry.request,
+                                outcome
+                            );
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
             }
         }
+        Subcommand::Completions(_) => unreachable!("handled before connecting"),
         Subcommand::Shutdown(shutdown) => {
             println!("Shutting down {:?}", shutdown.id);
         }
+        Subcommand::Pair(pair) => {
+            let node = pair.ticket.node.clone();
+            let node_id = node.node_id;
+            let client = create_client().await?;
+            let request = Request::Pair(pair.ticket.secret);
+            let role = call::<munin_proto::Role>(&client, node.clone(), &request).await??;
+            println!("Paired with {} as {}", node_id, role);
+            // the ticket is used up, so save an alias even if the name is unknown
+            let name = match pair.name {
+                Some(name) => name,
+                None => {
+                    let name = match get_version(&client, node.clone()).await {
+                        Ok(version) => version.name,
+                        Err(e) => {
+                            println!("Failed to get the name of {}: {}", node_id, e);
+                            node_id.to_string()
+                        }
+                    };
+                    if let Some(other) = alias_owner(&config, &name, node_id) {
+                        println!(
+                            "Not saving an alias, {} is taken by {}, use add-node --name",
+                            name, other
+                        );
+                        return Ok(());
+                    }
+                    name
+                }
+            };
+            println!("Saving alias {}", name);
+            config.nodes.insert(name, node);
+            config.save()?;
+        }
+        Subcommand::Delegate(delegate) => {
+            let expires = match delegate.until {
+                Some(until) => until,
+                None => SystemTime::now()
+                    .checked_add(delegate.duration)
+                    .context("--for is too long")?,
+            };
+            let delegation = Delegation {
+                issuer: config.secret_key.public(),
+                controller: delegate.controller,
+                role: delegate.role,
+                expires,
+                requests: delegate.only.map(|only| only.into_iter().collect()),
+            };
+            // if we have a delegation ourselves, we pass on a part of it
+            let mut chain = config
+                .delegation
+                .clone()
+                .unwrap_or(DelegationChain(Vec::new()));
+            chain.0.push(delegation.sign(&config.secret_key));
+            anyhow::ensure!(
+                chain.0.len() <= munin_proto::MAX_CHAIN_LEN,
+                "delegation chain would be too long"
+            );
+            let owner = chain.0[0].delegation.issuer;
+            let grant = chain.verify(owner, delegate.controller, SystemTime::now())?;
+            println!(
+                "Delegation from {} for {} as {}:",
+                owner,
+                delegate.controller,
+                format_grant(&grant)
+            );
+            println!("{}", chain);
+            println!("On the controller, run: munin use-delegation <delegation>");
+        }
+        Subcommand::UseDelegation(use_delegation) => {
+            let chain = use_delegation.delegation;
+            let me = config.secret_key.public();
+            anyhow::ensure!(
+                chain.controller() == Some(me),
+                "this delegation is not for {}",
+                me
+            );
+            let owner = chain.0[0].delegation.issuer;
+            let grant = chain.verify(owner, me, SystemTime::now())?;
+            println!(
+                "Using delegation from {} as {}",
+                owner,
+                format_grant(&grant)
+            );
+            config.delegation = Some(chain);
+            config.save()?;
+        }
         Subcommand::AddNode(add_node) => {
-            config.nodes.insert(add_node.name, add_node.addr);
+            let addr = add_node.node_addr(&args.addr);
+            let name = match &add_node.name {
+                Some(name) => name.clone(),
+                None => {
+                    let client = create_client().await?;
+                    let name = get_version(&client, addr.clone()).await?.name;
+                    if let Some(other) = alias_owner(&config, &name, addr.node_id) {
+                        anyhow::bail!("alias {} is taken by {}, use --name", name, other);
+                    }
+                    println!("Saving alias {}", name);
+                    name
+                }
+            };
+            config.nodes.insert(name, addr);
             config.save()?;
         }
+        Subcommand::RefreshNodes(refresh_nodes) => {
+            let nodes = get_nodes(refresh_nodes.id, &config, &args.addr)?;
+            let client = create_client().await?;
+            let mut changed = false;
+            for (label, addr) in nodes {
+                let name = match get_version(&client, addr.clone()).await {
+                    Ok(version) => version.name,
+                    Err(e) => {
+                        println!("{}: {}", label, e);
+                        continue;
+                    }
+                };
+                // an alias given on the command line, or else any alias of the node
+                let alias = if config.nodes.contains_key(&label) {
+                    Some(label)
+                } else {
+                    config
+                        .nodes
+                        .iter()
+                        .find(|(_, known)| known.node_id == addr.node_id)
+                        .map(|(alias, _)| alias.clone())
+                };
+                if alias.as_ref() == Some(&name) {
+                    println!("{}: up to date", name);
+                    continue;
+                }
+                if let Some(other) = config.nodes.get(&name) {
+                    if other.node_id != addr.node_id {
+                        println!(
+                            "{}: alias {} is taken by {}",
+                            addr.node_id, name, other.node_id
+                        );
+                    } else if let Some(alias) = alias {
+                        // keep the alias with the right name, and its addresses
+                        println!("Removing alias {}, the node is {}", alias, name);
+                        config.nodes.remove(&alias);
+                        changed = true;
+                    }
+                    continue;
+                }
+                let addr = match alias {
+                    Some(alias) => {
+                        println!("Renaming alias {} to {}", alias, name);
+                        config.nodes.remove(&alias).expect("alias exists")
+                    }
+                    None => {
+                        println!("Saving alias {} for {}", name, addr.node_id);
+                        addr
+                    }
+                };
+                config.nodes.insert(name, addr);
+                changed = true;
+            }
+            if changed {
+                config.save()?;
+            }
+        }
+        Subcommand::SetName(set_name) => {
+            let nodes = get_nodes(vec![set_name.id], &config, &args.addr)?;
+            let client = create_client().await?;
+            for (name, id) in nodes {
+                println!("Renaming {} to {}", name, set_name.name);
+                let request = Request::SetName(set_name.name.clone());
+                let msg = call::<()>(&client, id, &request).await?;
+                match msg {
+                    Ok(()) => println!("OK, run refresh-nodes to rename the alias"),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+        }
         Subcommand::RemoveNode(remove_node) => {
             if config.nodes.remove(&remove_node.name).is_some() {
                 config.save()?;
@@ -157,9 +856,80 @@ async fn main_impl(mut config: Config) -> anyhow::Result<()> {
                 println!("No alias with name {}", remove_node.name);
             }
         }
+        Subcommand::Discover(discover) => {
+            let mut found = BTreeMap::new();
+            munin_proto::query_local(discover.wait, |announcement| {
+                found.insert(announcement.node_id, announcement);
+                false
+            })
+            .await?;
+            if found.is_empty() {
+                println!("No daemons found on the local network");
+                return Ok(());
+            }
+            let client = create_client().await?;
+            let mut changed = false;
+            for announcement in found.into_values() {
+                let node_id = announcement.node_id;
+                let addr = NodeAddr {
+                    node_id,
+                    info: announcement.addr_info,
+                };
+                let status = access_status(&client, addr.clone(), discover.request_access).await;
+                let alias = config
+                    .nodes
+                    .iter()
+                    .find(|(_, known)| known.node_id == node_id)
+                    .map(|(alias, _)| alias.clone());
+                let alias_text = alias
+                    .as_ref()
+                    .map(|alias| format!(", alias {}", alias))
+                    .unwrap_or_default();
+                println!(
+                    "\"{}\" {}: {}{}",
+                    announcement.name, node_id, status, alias_text
+                );
+                if !discover.add {
+                    continue;
+                }
+                match alias {
+                    Some(alias) => {
+                        if config.nodes.get(&alias) != Some(&addr) {
+                            println!("  Updating the addresses of alias {}", alias);
+                            config.nodes.insert(alias, addr);
+                            changed = true;
+                        }
+                    }
+                    None if announcement.name.is_empty() => {
+                        println!("  Not saving an alias, the daemon has no name");
+                    }
+                    None if config.nodes.contains_key(&announcement.name) => {
+                        println!(
+                            "  Not saving an alias, {} is taken, use add-node --name",
+                            announcement.name
+                        );
+                    }
+                    None => {
+                        println!("  Saving alias {}", announcement.name);
+                        config.nodes.insert(announcement.name, addr);
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                config.save()?;
+            }
+        }
         Subcommand::ListNodes(_) => {
-            for (name, id) in &config.nodes {
-                println!("{}: {}", name, id);
+            for (name, addr) in &config.nodes {
+                let mut line = format!("{}: {}", name, addr.node_id);
+                if let Some(relay) = &addr.info.relay_url {
+                    line.push_str(&format!(" relay {}", relay));
+                }
+                for direct in &addr.info.direct_addresses {
+                    line.push_str(&format!(" {}", direct));
+                }
+                println!("{}", line);
             }
         }
     }
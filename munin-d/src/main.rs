@@ -1,8 +1,110 @@
+use std::time::Duration;
+
+use clap::Parser;
+use iroh_net::NodeAddr;
+use munin_proto::{PairingTicket, Role};
+use munin_server::{Config, Control, Pairings};
+use qrcode::{render::unicode, QrCode};
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Parser)]
+struct Args {
+    #[clap(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(Debug, Parser)]
+enum Subcommand {
+    /// Run the daemon, the default if no subcommand is given
+    Run,
+    /// Print a one-time ticket that lets a controller add itself with `munin pair`
+    Pair(Pair),
+}
+
+#[derive(Debug, Parser)]
+struct Pair {
+    /// Role of the new controller: viewer, operator or admin
+    #[clap(long, default_value = "viewer")]
+    role: Role,
+    /// How long the ticket is valid, e.g. 10m
+    #[clap(long, default_value = "10m", value_parser = humantime::parse_duration)]
+    ttl: Duration,
+}
+
+/// Shut down on SIGTERM and SIGINT, and reload the config on SIGHUP
+#[cfg(unix)]
+fn forward_signals(send: UnboundedSender<Control>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        loop {
+            let control = tokio::select! {
+                _ = terminate.recv() => {
+                    tracing::info!("Received SIGTERM");
+                    Control::Shutdown
+                }
+                _ = interrupt.recv() => {
+                    tracing::info!("Received SIGINT");
+                    Control::Shutdown
+                }
+                _ = hangup.recv() => {
+                    tracing::info!("Received SIGHUP");
+                    Control::Reload
+                }
+            };
+            if send.send(control).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Shut down on ctrl-c
+#[cfg(not(unix))]
+fn forward_signals(send: UnboundedSender<Control>) -> anyhow::Result<()> {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if send.send(Control::Shutdown).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    let config = munin_server::Config::get_or_create()?;
-    let (_send, recv) = tokio::sync::mpsc::unbounded_channel();
-    munin_server::run(config, recv).await?;
+    let args = Args::parse();
+    let config = Config::get_or_create()?;
+    match args.subcommand.unwrap_or(Subcommand::Run) {
+        Subcommand::Run => {
+            let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+            forward_signals(send)?;
+            munin_server::run(config, recv).await?;
+        }
+        Subcommand::Pair(pair) => {
+            let pairings = Pairings::new(Config::pairings_path()?);
+            let secret = pairings.create(pair.role, pair.ttl)?;
+            let ticket = PairingTicket {
+                node: NodeAddr::new(config.secret_key.public()),
+                secret,
+            };
+            let ticket = ticket.to_string();
+            let code = QrCode::new(&ticket)?;
+            println!("{}", code.render::<unicode::Dense1x2>().build());
+            println!("{}", ticket);
+            println!();
+            println!(
+                "Run `munin pair <ticket>` within {} to add a controller as {}.",
+                humantime::format_duration(pair.ttl),
+                pair.role
+            );
+            println!("The ticket works once, and only while the daemon is running.");
+        }
+    }
     Ok(())
 }
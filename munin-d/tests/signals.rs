@@ -0,0 +1,55 @@
+#![cfg(unix)]
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use iroh_net::key::SecretKey;
+use munin_proto::RelayConfig;
+use munin_server::{Config, Network};
+
+#[test]
+fn test_sigterm() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::env::set_var("MUNIN_DATA_DIR", dir.path());
+    Config {
+        network: Network {
+            discovery: Default::default(),
+            relay: RelayConfig::Disabled,
+            ..Default::default()
+        },
+        ..Config::new(SecretKey::generate())
+    }
+    .save()?;
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_munin-d"))
+        .env("MUNIN_DATA_DIR", dir.path())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // the signal handlers are installed before the daemon says who it is
+    // keep stdout open until the end, the daemon still prints its ticket
+    let mut stdout = BufReader::new(daemon.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    assert!(line.starts_with("I am"), "{}", line);
+
+    let status = Command::new("kill")
+        .args(["-TERM", &daemon.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = daemon.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            daemon.kill()?;
+            panic!("the daemon did not shut down");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    assert!(status.success());
+    let audit = std::fs::read_to_string(Config::audit_log_path()?)?;
+    assert!(audit.contains("DaemonShutdown"), "{}", audit);
+    Ok(())
+}
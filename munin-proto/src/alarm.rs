@@ -0,0 +1,136 @@
+//! Alarms that the daemon fires on its own, at a fixed time of day.
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AudioOutput, AudioSource};
+
+/// A time of day, in the local time of the daemon
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid time, expected HH:MM: {}", s);
+        let (hour, minute) = s.split_once(':').ok_or_else(err)?;
+        let hour = hour.parse::<u8>().map_err(|_| err())?;
+        let minute = minute.parse::<u8>().map_err(|_| err())?;
+        if hour > 23 || minute > 59 {
+            return Err(err());
+        }
+        Ok(Self { hour, minute })
+    }
+}
+
+/// A set of days of the week
+///
+/// Bit 0 is monday, bit 6 is sunday.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Weekdays(u8);
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Weekdays {
+    pub const EVERY_DAY: Self = Self(0b111_1111);
+    /// Monday to friday
+    pub const WEEKDAYS: Self = Self(0b001_1111);
+    /// Saturday and sunday
+    pub const WEEKEND: Self = Self(0b110_0000);
+
+    /// Check if the set contains a day, counted from monday = 0
+    pub fn contains(&self, days_from_monday: u32) -> bool {
+        days_from_monday < 7 && self.0 & (1 << days_from_monday) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for Weekdays {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::EVERY_DAY => write!(f, "daily"),
+            Self::WEEKDAYS => write!(f, "weekdays"),
+            Self::WEEKEND => write!(f, "weekend"),
+            _ => {
+                let days = (0..7)
+                    .filter(|day| self.contains(*day))
+                    .map(|day| DAY_NAMES[day as usize])
+                    .collect::<Vec<_>>();
+                write!(f, "{}", days.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for Weekdays {
+    type Err = String;
+
+    /// Parse "daily", "weekdays", "weekend" or a comma separated list of day names
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "daily" => return Ok(Self::EVERY_DAY),
+            "weekdays" => return Ok(Self::WEEKDAYS),
+            "weekend" | "weekends" => return Ok(Self::WEEKEND),
+            _ => {}
+        }
+        let mut days = 0u8;
+        for day in s.split(',') {
+            let day = day.trim().to_lowercase();
+            let index = DAY_NAMES
+                .iter()
+                .position(|name| day.starts_with(name))
+                .ok_or_else(|| format!("Invalid day: {}", day))?;
+            days |= 1 << index;
+        }
+        Ok(Self(days))
+    }
+}
+
+/// What an alarm does and when
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AlarmSpec {
+    pub at: TimeOfDay,
+    pub days: Weekdays,
+    pub source: AudioSource,
+    /// Amplification factor, the daemon picks a default if not set
+    pub volume: Option<f32>,
+    pub output: AudioOutput,
+}
+
+impl fmt::Display for AlarmSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.at, self.days, self.source)?;
+        if let Some(volume) = self.volume {
+            write!(f, " volume {}", volume)?;
+        }
+        if self.output != AudioOutput::Default {
+            write!(f, " on {}", self.output)?;
+        }
+        Ok(())
+    }
+}
+
+/// An alarm stored on the daemon
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Alarm {
+    pub id: u32,
+    pub spec: AlarmSpec,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAlarmsResponse {
+    pub alarms: Vec<Alarm>,
+}
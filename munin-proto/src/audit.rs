@@ -0,0 +1,49 @@
+//! The audit log of a daemon, see [crate::Request::GetAuditLog].
+use std::time::SystemTime;
+
+use iroh_net::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// A request a daemon received, and what came of it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    pub node: NodeId,
+    /// Short description of the request
+    pub request: String,
+    pub outcome: AuditOutcome,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AuditOutcome {
+    Ok,
+    Error(Error),
+    /// The node is not allowed to connect at all
+    Unauthorized,
+}
+
+/// Which entries to return, all filters are optional
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuditQuery {
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    pub node: Option<NodeId>,
+    /// Return at most this many entries, the most recent ones
+    pub limit: Option<u32>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time <= until)
+            && self.node.is_none_or(|node| entry.node == node)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLogResponse {
+    /// Matching entries, oldest first
+    pub entries: Vec<AuditEntry>,
+}
@@ -0,0 +1,154 @@
+//! Certificates that let an owner key grant access to other controllers,
+//! see [crate::Request::Delegated].
+use std::{collections::BTreeSet, fmt, str::FromStr, time::SystemTime};
+
+use anyhow::{anyhow, ensure};
+use iroh_base::ticket::{self, Ticket};
+use iroh_net::{
+    key::{SecretKey, Signature},
+    NodeId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Grant, RequestKind, Role};
+
+/// Longer chains are rejected, to keep requests small
+pub const MAX_CHAIN_LEN: usize = 4;
+
+/// Prefix of the signed data, so the signature can't be mistaken for anything else
+const SIGNATURE_CONTEXT: &[u8] = b"munin-delegation";
+
+/// Access granted by the issuer to the controller
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Delegation {
+    pub issuer: NodeId,
+    pub controller: NodeId,
+    pub role: Role,
+    /// Unlike grants, delegations always expire since they can't be revoked
+    pub expires: SystemTime,
+    /// If set, only these kinds of requests are allowed
+    pub requests: Option<BTreeSet<RequestKind>>,
+}
+
+impl Delegation {
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = SIGNATURE_CONTEXT.to_vec();
+        data.extend(postcard::to_allocvec(self).expect("postcard serialization failed"));
+        data
+    }
+
+    /// Sign the delegation, the key must be the one of the issuer
+    pub fn sign(self, key: &SecretKey) -> DelegationCert {
+        assert_eq!(
+            key.public(),
+            self.issuer,
+            "delegation signed by another key"
+        );
+        let signature = key.sign(&self.signed_data());
+        DelegationCert {
+            delegation: self,
+            signature,
+        }
+    }
+}
+
+/// A [Delegation] with the signature of its issuer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DelegationCert {
+    pub delegation: Delegation,
+    pub signature: Signature,
+}
+
+impl DelegationCert {
+    pub fn verify_signature(&self) -> anyhow::Result<()> {
+        self.delegation
+            .issuer
+            .verify(&self.delegation.signed_data(), &self.signature)
+            .map_err(|_| anyhow!("invalid signature by {}", self.delegation.issuer))
+    }
+}
+
+/// Certificates leading from an owner to a controller
+///
+/// The first one is issued by the owner, every further one by the controller
+/// of the one before. Each step can only narrow down what the previous one allowed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DelegationChain(pub Vec<DelegationCert>);
+
+impl DelegationChain {
+    /// The controller at the end of the chain
+    pub fn controller(&self) -> Option<NodeId> {
+        self.0.last().map(|cert| cert.delegation.controller)
+    }
+
+    /// Verify that the chain leads from `owner` to `controller`, and return what it grants
+    pub fn verify(
+        &self,
+        owner: NodeId,
+        controller: NodeId,
+        now: SystemTime,
+    ) -> anyhow::Result<Grant> {
+        ensure!(!self.0.is_empty(), "empty delegation chain");
+        ensure!(self.0.len() <= MAX_CHAIN_LEN, "delegation chain too long");
+        let mut issuer = owner;
+        let mut grant = Grant {
+            role: Role::Admin,
+            expires: None,
+            requests: None,
+        };
+        for cert in &self.0 {
+            let delegation = &cert.delegation;
+            ensure!(
+                delegation.issuer == issuer,
+                "delegation issued by {}, expected {}",
+                delegation.issuer,
+                issuer
+            );
+            cert.verify_signature()?;
+            ensure!(delegation.expires > now, "delegation has expired");
+            grant.role = grant.role.min(delegation.role);
+            grant.expires = Some(match grant.expires {
+                Some(expires) => expires.min(delegation.expires),
+                None => delegation.expires,
+            });
+            grant.requests = match (grant.requests, &delegation.requests) {
+                (Some(a), Some(b)) => Some(a.intersection(b).copied().collect()),
+                (a, b) => a.or_else(|| b.clone()),
+            };
+            issuer = delegation.controller;
+        }
+        ensure!(
+            issuer == controller,
+            "delegation is for {}, not {}",
+            issuer,
+            controller
+        );
+        Ok(grant)
+    }
+}
+
+impl Ticket for DelegationChain {
+    const KIND: &'static str = "munindelegation";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).expect("postcard serialization failed")
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ticket::Error> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
+
+impl fmt::Display for DelegationChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+
+impl FromStr for DelegationChain {
+    type Err = ticket::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ticket::deserialize(s)
+    }
+}
@@ -0,0 +1,72 @@
+//! Errors returned by the daemon, as the `Err` side of every response.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Capability, RequestKind, Role};
+
+/// Why a request failed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Error {
+    /// The role of the controller does not allow the request
+    PermissionDenied { role: Role, required: Role },
+    /// The grant of the controller is limited to other kinds of requests
+    NotGranted(RequestKind),
+    /// The daemon was built without a feature the request needs
+    Unsupported(Capability),
+    /// The request was allowed, but failed
+    Failed(String),
+    /// The node is unknown to the daemon, an admin has to approve it first
+    PendingApproval,
+    /// An admin has denied access to the node
+    AccessDenied,
+    /// The node is unknown to the daemon, and its delegation chain is not valid
+    InvalidDelegation(String),
+    /// The process is on the protected list of the daemon, e.g. init or the display server
+    ProtectedProcess { pid: u32, name: String },
+    /// The daemon gave up on the request, see the `[timeouts]` of its config
+    Timeout,
+    /// The node is unknown to the daemon, and has not requested access
+    NotAllowed,
+}
+
+impl Error {
+    pub fn failed(e: impl fmt::Display) -> Self {
+        Self::Failed(e.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PermissionDenied { role, required } => write!(
+                f,
+                "permission denied: this request needs role {}, but you are {}",
+                required, role
+            ),
+            Error::NotGranted(kind) => {
+                write!(f, "permission denied: your grant does not include {}", kind)
+            }
+            Error::Unsupported(capability) => {
+                write!(f, "{:?} is not supported by this daemon", capability)
+            }
+            Error::Failed(message) => write!(f, "{}", message),
+            Error::PendingApproval => write!(f, "access is pending approval by an admin"),
+            Error::AccessDenied => write!(f, "access was denied by an admin"),
+            Error::InvalidDelegation(reason) => write!(f, "invalid delegation: {}", reason),
+            Error::ProtectedProcess { pid, name } if name.is_empty() => {
+                write!(f, "process {} is protected", pid)
+            }
+            Error::ProtectedProcess { pid, name } => {
+                write!(f, "process {} ({}) is protected", pid, name)
+            }
+            Error::Timeout => write!(f, "the request timed out"),
+            Error::NotAllowed => write!(f, "the node is not allowed, and has not requested access"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The daemon answers every request with a [Response] of the matching type
+pub type Response<T> = Result<T, Error>;
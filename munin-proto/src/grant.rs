@@ -0,0 +1,216 @@
+//! What an allowed node may do, and for how long.
+use std::{collections::BTreeSet, fmt, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Request, Role};
+
+/// The kind of a [Request], without its arguments
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RequestKind {
+    GetVersion,
+    ListProcesses,
+    KillProcess,
+    PlayAudio,
+    StopAudio,
+    ListSounds,
+    ListAudioDevices,
+    AddAlarm,
+    ListAlarms,
+    RemoveAlarm,
+    GetSystemInfo,
+    Shutdown,
+    ListAllowedNodes,
+    AllowNode,
+    DisallowNode,
+    GetAuditLog,
+    Pair,
+    RequestAccess,
+    ListAccessRequests,
+    ApproveAccess,
+    DenyAccess,
+    GetStats,
+    SetName,
+}
+
+impl RequestKind {
+    pub const ALL: [RequestKind; 23] = [
+        RequestKind::GetVersion,
+        RequestKind::ListProcesses,
+        RequestKind::KillProcess,
+        RequestKind::PlayAudio,
+        RequestKind::StopAudio,
+        RequestKind::ListSounds,
+        RequestKind::ListAudioDevices,
+        RequestKind::AddAlarm,
+        RequestKind::ListAlarms,
+        RequestKind::RemoveAlarm,
+        RequestKind::GetSystemInfo,
+        RequestKind::Shutdown,
+        RequestKind::ListAllowedNodes,
+        RequestKind::AllowNode,
+        RequestKind::DisallowNode,
+        RequestKind::GetAuditLog,
+        RequestKind::Pair,
+        RequestKind::RequestAccess,
+        RequestKind::ListAccessRequests,
+        RequestKind::ApproveAccess,
+        RequestKind::DenyAccess,
+        RequestKind::GetStats,
+        RequestKind::SetName,
+    ];
+
+    /// The name used in configs and on the command line, e.g. `play-audio`
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestKind::GetVersion => "get-version",
+            RequestKind::ListProcesses => "list-processes",
+            RequestKind::KillProcess => "kill-process",
+            RequestKind::PlayAudio => "play-audio",
+            RequestKind::StopAudio => "stop-audio",
+            RequestKind::ListSounds => "list-sounds",
+            RequestKind::ListAudioDevices => "list-audio-devices",
+            RequestKind::AddAlarm => "add-alarm",
+            RequestKind::ListAlarms => "list-alarms",
+            RequestKind::RemoveAlarm => "remove-alarm",
+            RequestKind::GetSystemInfo => "get-system-info",
+            RequestKind::Shutdown => "shutdown",
+            RequestKind::ListAllowedNodes => "list-allowed-nodes",
+            RequestKind::AllowNode => "allow-node",
+            RequestKind::DisallowNode => "disallow-node",
+            RequestKind::GetAuditLog => "get-audit-log",
+            RequestKind::Pair => "pair",
+            RequestKind::RequestAccess => "request-access",
+            RequestKind::ListAccessRequests => "list-access-requests",
+            RequestKind::ApproveAccess => "approve-access",
+            RequestKind::DenyAccess => "deny-access",
+            RequestKind::GetStats => "get-stats",
+            RequestKind::SetName => "set-name",
+        }
+    }
+
+    /// The lowest role that may make this kind of request
+    pub fn required_role(&self) -> Role {
+        match self {
+            RequestKind::GetVersion
+            | RequestKind::ListProcesses
+            | RequestKind::ListSounds
+            | RequestKind::ListAudioDevices
+            | RequestKind::ListAlarms
+            | RequestKind::GetSystemInfo
+            | RequestKind::Pair
+            | RequestKind::RequestAccess => Role::Viewer,
+            RequestKind::KillProcess
+            | RequestKind::PlayAudio
+            | RequestKind::StopAudio
+            | RequestKind::AddAlarm
+            | RequestKind::RemoveAlarm => Role::Operator,
+            RequestKind::Shutdown
+            | RequestKind::ListAllowedNodes
+            | RequestKind::AllowNode
+            | RequestKind::DisallowNode
+            | RequestKind::GetAuditLog
+            | RequestKind::ListAccessRequests
+            | RequestKind::ApproveAccess
+            | RequestKind::DenyAccess
+            | RequestKind::GetStats
+            | RequestKind::SetName => Role::Admin,
+        }
+    }
+}
+
+impl fmt::Display for RequestKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for RequestKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RequestKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("Invalid request kind: {}", s))
+    }
+}
+
+impl Request {
+    pub fn kind(&self) -> RequestKind {
+        match self {
+            Request::GetVersion => RequestKind::GetVersion,
+            Request::ListProcesses => RequestKind::ListProcesses,
+            Request::KillProcess(_) => RequestKind::KillProcess,
+            Request::PlayAudio(_) => RequestKind::PlayAudio,
+            Request::StopAudio => RequestKind::StopAudio,
+            Request::ListSounds => RequestKind::ListSounds,
+            Request::ListAudioDevices => RequestKind::ListAudioDevices,
+            Request::AddAlarm(_) => RequestKind::AddAlarm,
+            Request::ListAlarms => RequestKind::ListAlarms,
+            Request::RemoveAlarm(_) => RequestKind::RemoveAlarm,
+            Request::GetSystemInfo => RequestKind::GetSystemInfo,
+            Request::Shutdown => RequestKind::Shutdown,
+            Request::ListAllowedNodes => RequestKind::ListAllowedNodes,
+            Request::AllowNode { .. } => RequestKind::AllowNode,
+            Request::DisallowNode(_) => RequestKind::DisallowNode,
+            Request::GetAuditLog(_) => RequestKind::GetAuditLog,
+            Request::Pair(_) => RequestKind::Pair,
+            Request::RequestAccess { .. } => RequestKind::RequestAccess,
+            Request::ListAccessRequests => RequestKind::ListAccessRequests,
+            Request::ApproveAccess { .. } => RequestKind::ApproveAccess,
+            Request::DenyAccess(_) => RequestKind::DenyAccess,
+            Request::Delegated { request, .. } => request.kind(),
+            Request::GetStats => RequestKind::GetStats,
+            Request::SetName(_) => RequestKind::SetName,
+        }
+    }
+
+    /// The lowest role that may make this request
+    pub fn required_role(&self) -> Role {
+        self.kind().required_role()
+    }
+}
+
+/// The entry of an allowed node: its role, and optionally limits on top of it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub role: Role,
+    /// The grant is removed by the daemon after this time
+    pub expires: Option<SystemTime>,
+    /// If set, only these kinds of requests are allowed, as far as the role allows them
+    pub requests: Option<BTreeSet<RequestKind>>,
+}
+
+impl Grant {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// A grant without expiry or request limits
+    pub fn is_permanent(&self) -> bool {
+        self.expires.is_none() && self.requests.is_none()
+    }
+
+    /// Whether the request kinds of the grant include `kind`
+    ///
+    /// Asking for the version or the own role is always allowed, so the
+    /// controller can tell what it is talking to.
+    pub fn permits(&self, kind: RequestKind) -> bool {
+        matches!(kind, RequestKind::GetVersion | RequestKind::RequestAccess)
+            || self
+                .requests
+                .as_ref()
+                .is_none_or(|requests| requests.contains(&kind))
+    }
+}
+
+impl From<Role> for Grant {
+    fn from(role: Role) -> Self {
+        Self {
+            role,
+            expires: None,
+            requests: None,
+        }
+    }
+}
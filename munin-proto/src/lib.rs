@@ -1,30 +1,234 @@
-use std::{fmt, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::{Duration, SystemTime},
+};
 
+use iroh_net::NodeId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+mod alarm;
+pub use alarm::{Alarm, AlarmSpec, ListAlarmsResponse, TimeOfDay, Weekdays};
+
+mod audit;
+pub use audit::{AuditEntry, AuditLogResponse, AuditOutcome, AuditQuery};
+
+mod delegation;
+pub use delegation::{Delegation, DelegationCert, DelegationChain, MAX_CHAIN_LEN};
+
+mod error;
+pub use error::{Error, Response};
+
+mod grant;
+pub use grant::{Grant, RequestKind};
+
+mod migrate;
+pub use migrate::{backup_config, upgrade_config, Migration};
+
+mod local;
+pub use local::{query_local, Announcement, LocalDiscovery, LOCAL_DISCOVERY_GROUP};
+
+mod relay;
+pub use relay::RelayConfig;
+
+mod pairing;
+pub use pairing::{PairingSecret, PairingTicket, PAIRING_SECRET_LEN};
+
+/// A request from a controller
+///
+/// postcard encodes the variant by its index, so new variants go at the end.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Request {
     ListProcesses,
     KillProcess(u32),
-    PlayAudio(AudioSource),
+    PlayAudio(PlayAudioRequest),
     GetSystemInfo,
     Shutdown,
+    ListSounds,
+    StopAudio,
+    ListAudioDevices,
+    GetVersion,
+    AddAlarm(AlarmSpec),
+    ListAlarms,
+    RemoveAlarm(u32),
+    ListAllowedNodes,
+    /// Allow a node to connect, or replace its grant
+    AllowNode {
+        node: NodeId,
+        grant: Grant,
+    },
+    DisallowNode(NodeId),
+    GetAuditLog(AuditQuery),
+    /// Redeem a pairing secret to become an allowed node, answered with the granted role
+    ///
+    /// This is accepted from unknown nodes.
+    Pair(PairingSecret),
+    /// Ask to be allowed, answered with the role if we already are
+    ///
+    /// Unknown nodes get [Error::PendingApproval] for any request, this one
+    /// just lets them say who they are.
+    RequestAccess {
+        name: Option<String>,
+    },
+    ListAccessRequests,
+    /// Allow a node that has requested access
+    ApproveAccess {
+        node: NodeId,
+        role: Role,
+    },
+    /// Deny a node that has requested access, it will no longer be queued
+    DenyAccess(NodeId),
+    /// A request from a controller that presents a delegation from the owner of the daemon
+    ///
+    /// Nodes that are allowed directly use their own grant instead.
+    Delegated {
+        chain: DelegationChain,
+        request: Box<Request>,
+    },
+    GetStats,
+    /// Change the name of the daemon in its config
+    SetName(String),
+}
+
+/// What a controller is allowed to do, each role includes the ones before it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// List processes, sounds, alarms and system info
+    Viewer,
+    /// Play audio, manage alarms and kill processes
+    Operator,
+    /// Power off the machine and change the daemon config
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Invalid role: {}", s)),
+        }
+    }
+}
+
+/// Response to [Request::GetVersion]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub protocol_version: u32,
+    /// The name from the daemon's config, not unique
+    pub name: String,
+    /// Optional features the daemon was built with
+    pub capabilities: BTreeSet<Capability>,
+}
+
+/// An optional feature of a daemon
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    /// The daemon can play audio. Without it, all audio requests fail.
+    Audio,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayAudioRequest {
+    pub source: AudioSource,
+    /// Amplification factor, the daemon picks a default if not set
+    pub volume: Option<f32>,
+    /// If true, respond after the clip has finished instead of once it is queued
+    pub wait: bool,
+    pub output: AudioOutput,
+}
+
+/// Where to play a clip
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub enum AudioOutput {
+    /// The default output device of the daemon
+    #[default]
+    Default,
+    /// The output device with the given name
+    Device(String),
+    /// All output devices at the same time
+    All,
+}
+
+impl fmt::Display for AudioOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioOutput::Default => write!(f, "default"),
+            AudioOutput::Device(name) => write!(f, "device:{}", name),
+            AudioOutput::All => write!(f, "all"),
+        }
+    }
+}
+
+impl std::str::FromStr for AudioOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(AudioOutput::Default),
+            "all" => Ok(AudioOutput::All),
+            _ => match s.strip_prefix("device:") {
+                Some(name) => Ok(AudioOutput::Device(name.to_string())),
+                None => Err(format!("Invalid audio output: {}", s)),
+            },
+        }
+    }
 }
 
+/// Outcome of [Request::PlayAudio] for a single output device
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DevicePlayback {
+    pub device: String,
+    pub result: Result<PlaybackStatus, String>,
+}
+
+/// Response to [Request::PlayAudio]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    /// The clip was queued and will play after the clips before it
+    Queued,
+    /// The clip played to the end
+    Finished,
+    /// The clip was cut off by [Request::StopAudio]
+    Stopped,
+}
+
+/// Names of the sounds that are embedded in every daemon
+pub const BUILTIN_SOUNDS: &[&str] = &["wakeup", "alarm", "rickroll", "gotobed"];
+
+/// Normalize a sound name so that e.g. "go_to_bed", "GoToBed" and "gotobed" all match
+pub fn normalize_sound_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum AudioSource {
-    WakeUp,
-    Alarm,
-    RickRoll,
+    /// A sound from the catalog of the daemon, by normalized name
+    Sound(String),
     Url(String),
 }
 
 impl fmt::Display for AudioSource {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            AudioSource::WakeUp => write!(f, "WakeUp"),
-            AudioSource::Alarm => write!(f, "Alarm"),
-            AudioSource::RickRoll => write!(f, "RickRoll"),
+            AudioSource::Sound(name) => write!(f, "{}", name),
             AudioSource::Url(url) => write!(f, "Url({})", url),
         }
     }
@@ -34,16 +238,15 @@ impl std::str::FromStr for AudioSource {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "wakeup" => Ok(AudioSource::WakeUp),
-            "alarm" => Ok(AudioSource::Alarm),
-            "rickroll" => Ok(AudioSource::RickRoll),
-            _ if s.starts_with("url(") && s.ends_with(')') => {
-                let url = &s[4..s.len() - 1]; // Extract the URL inside the "Url()" format
-                Ok(AudioSource::Url(url.to_string()))
-            }
-            _ => Err(format!("Invalid string: {}", s)),
+        if s.to_lowercase().starts_with("url(") && s.ends_with(')') {
+            let url = &s[4..s.len() - 1]; // Extract the URL inside the "Url()" format
+            return Ok(AudioSource::Url(url.to_string()));
         }
+        let name = normalize_sound_name(s);
+        if name.is_empty() {
+            return Err(format!("Invalid string: {}", s));
+        }
+        Ok(AudioSource::Sound(name))
     }
 }
 
@@ -52,12 +255,97 @@ pub struct ListProcessesResponse {
     pub tasks: Vec<(u32, String)>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListSoundsResponse {
+    pub sounds: Vec<SoundInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SoundInfo {
+    pub name: String,
+    /// true if the sound is embedded in the daemon, false if it was provided by the user
+    pub builtin: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAudioDevicesResponse {
+    pub devices: Vec<AudioDevice>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AudioDevice {
+    pub name: String,
+    /// true if this is the default output device
+    pub default: bool,
+}
+
+/// An unknown node that has tried to connect
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccessRequest {
+    pub node: NodeId,
+    /// Name the node gave itself, not verified in any way
+    pub name: Option<String>,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub attempts: u32,
+    pub denied: bool,
+}
+
+/// Response to [Request::ListAccessRequests]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAccessRequestsResponse {
+    pub requests: Vec<AccessRequest>,
+}
+
+/// Response to [Request::ListAllowedNodes]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAllowedNodesResponse {
+    pub nodes: BTreeMap<NodeId, Grant>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SysInfoResponse {
+    /// The name from the daemon's config
+    pub name: String,
     pub hostname: String,
     pub uptime: Duration,
 }
 
+/// Response to [Request::GetStats]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    /// Time since the daemon was started
+    pub uptime: Duration,
+    pub connections: ConnectionStats,
+}
+
+/// Counters of incoming connections, since the daemon was started
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConnectionStats {
+    /// Connections being handled right now
+    pub active: u32,
+    pub accepted: u64,
+    /// Refused because of the global limits
+    pub refused: u64,
+    /// Refused because of the limits per node
+    pub refused_node: u64,
+    /// Refused because the node or its address was banned
+    pub refused_banned: u64,
+    /// Bans of unknown nodes that kept trying
+    pub bans: u64,
+    /// Nodes that are banned right now
+    pub banned: BTreeMap<NodeId, BannedNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BannedNode {
+    pub remaining: Duration,
+    /// How often the node was banned, each ban is twice as long as the one before
+    pub bans: u32,
+}
+
 pub const ALPN: &[u8] = b"munin";
-pub const MAX_REQUEST_SIZE: usize = 1024;
+/// Version of the protocol, returned by [Request::GetVersion]
+pub const PROTOCOL_VERSION: u32 = 8;
+pub const MAX_REQUEST_SIZE: usize = 4096;
 pub const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
@@ -0,0 +1,309 @@
+//! Finding daemons on the local network without any servers.
+//!
+//! Controllers send a query to a multicast group, and every daemon in the
+//! group answers with its name and addresses, signed with its key.
+//!
+//! So daemons can't be used to amplify traffic towards a spoofed address,
+//! they never answer with more bytes than the query had, and answer at most
+//! a few queries per second.
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use futures::stream::{BoxStream, StreamExt};
+use iroh_net::{
+    discovery::{Discovery, DiscoveryItem},
+    key::{SecretKey, Signature},
+    AddrInfo, Endpoint, NodeId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+/// Multicast group the daemons listen on, in the organization local scope
+pub const LOCAL_DISCOVERY_GROUP: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 77), 47707);
+
+/// Shown as the source of addresses found this way
+const PROVENANCE: &str = "munin.local";
+/// Prefix of the signed data, so the signature can't be mistaken for anything else
+const SIGNATURE_CONTEXT: &[u8] = b"munin-local-discovery";
+/// How often a query is repeated while waiting for answers, datagrams get lost
+const QUERY_INTERVAL: Duration = Duration::from_millis(500);
+/// How long to wait for a daemon when connecting to it
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Larger than any announcement
+const MAX_MESSAGE_SIZE: usize = 2048;
+/// Queries are padded to this size, answers must not be larger
+const QUERY_SIZE: usize = 1200;
+/// Pause after a failed receive, so a broken socket doesn't spin
+const RECV_ERROR_DELAY: Duration = Duration::from_millis(100);
+/// Further queries in the same second are ignored
+const MAX_ANSWERS_PER_SECOND: u32 = 20;
+/// Longer names are cut off, so the announcement still fits
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    /// The padding makes the query as large as an answer
+    Query {
+        padding: Vec<u8>,
+    },
+    Announce(Box<Announcement>),
+}
+
+/// The name and addresses of a daemon, signed by it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Announcement {
+    pub node_id: NodeId,
+    /// The name from the daemon's config, not unique
+    pub name: String,
+    pub addr_info: AddrInfo,
+    signature: Signature,
+}
+
+impl Announcement {
+    fn signed_data(node_id: &NodeId, name: &str, addr_info: &AddrInfo) -> Vec<u8> {
+        let mut data = SIGNATURE_CONTEXT.to_vec();
+        data.extend(
+            postcard::to_allocvec(&(node_id, name, addr_info))
+                .expect("postcard serialization failed"),
+        );
+        data
+    }
+
+    fn new(key: &SecretKey, name: &str, addr_info: AddrInfo) -> Self {
+        let node_id = key.public();
+        let name = name
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_NAME_LEN)
+            .collect::<String>();
+        let signature = key.sign(&Self::signed_data(&node_id, &name, &addr_info));
+        Self {
+            node_id,
+            name,
+            addr_info,
+            signature,
+        }
+    }
+
+    fn verify(&self) -> anyhow::Result<()> {
+        self.node_id
+            .verify(
+                &Self::signed_data(&self.node_id, &self.name, &self.addr_info),
+                &self.signature,
+            )
+            .map_err(|_| anyhow!("invalid signature by {}", self.node_id))
+    }
+}
+
+/// Local network discovery as an iroh [Discovery] service
+///
+/// Created with [LocalDiscovery::announce] on daemons, which also resolve,
+/// and with [LocalDiscovery::resolve_only] on controllers.
+#[derive(Debug)]
+pub struct LocalDiscovery {
+    /// Our current addresses, set by the endpoint
+    addr_info: Arc<Mutex<Option<AddrInfo>>>,
+    responder: Option<JoinHandle<()>>,
+}
+
+impl LocalDiscovery {
+    /// Answer queries for our addresses, needs a tokio runtime
+    ///
+    /// `name` is called for every answer, so renaming the daemon takes effect
+    /// right away.
+    pub fn announce(
+        key: SecretKey,
+        name: impl Fn() -> String + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let socket =
+            std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, LOCAL_DISCOVERY_GROUP.port()))
+                .context("binding the local discovery port")?;
+        socket
+            .join_multicast_v4(LOCAL_DISCOVERY_GROUP.ip(), &Ipv4Addr::UNSPECIFIED)
+            .context("joining the local discovery group")?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        let addr_info = Arc::new(Mutex::new(None));
+        let responder = tokio::spawn(respond(socket, key, name, addr_info.clone()));
+        Ok(Self {
+            addr_info,
+            responder: Some(responder),
+        })
+    }
+
+    /// Only find other nodes, without announcing ourselves
+    pub fn resolve_only() -> Self {
+        Self {
+            addr_info: Default::default(),
+            responder: None,
+        }
+    }
+}
+
+impl Drop for LocalDiscovery {
+    fn drop(&mut self) {
+        if let Some(responder) = self.responder.take() {
+            responder.abort();
+        }
+    }
+}
+
+impl Discovery for LocalDiscovery {
+    fn publish(&self, info: &AddrInfo) {
+        *self.addr_info.lock().unwrap() = Some(info.clone());
+    }
+
+    fn resolve(
+        &self,
+        _endpoint: Endpoint,
+        node_id: NodeId,
+    ) -> Option<BoxStream<'static, anyhow::Result<DiscoveryItem>>> {
+        let resolve = async move {
+            let mut found = None;
+            query_local(RESOLVE_TIMEOUT, |announcement| {
+                if announcement.node_id == node_id {
+                    found = Some(announcement);
+                }
+                found.is_some()
+            })
+            .await?;
+            let announcement =
+                found.ok_or_else(|| anyhow!("{} not found on the local network", node_id))?;
+            Ok(DiscoveryItem {
+                node_id,
+                provenance: PROVENANCE,
+                last_updated: None,
+                addr_info: announcement.addr_info,
+            })
+        };
+        Some(futures::stream::once(resolve).boxed())
+    }
+}
+
+async fn respond(
+    socket: UdpSocket,
+    key: SecretKey,
+    name: impl Fn() -> String,
+    addr_info: Arc<Mutex<Option<AddrInfo>>>,
+) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    let mut window_start = Instant::now();
+    let mut answers = 0;
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(res) => res,
+            Err(e) => {
+                // e.g. an ICMP port unreachable for an earlier answer, WSAECONNRESET on windows
+                tracing::debug!("Receiving local discovery queries failed: {}", e);
+                tokio::time::sleep(RECV_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        let Ok(Message::Query { .. }) = postcard::from_bytes(&buf[..len]) else {
+            continue;
+        };
+        let now = Instant::now();
+        if now.duration_since(window_start) >= Duration::from_secs(1) {
+            window_start = now;
+            answers = 0;
+        }
+        if answers >= MAX_ANSWERS_PER_SECOND {
+            tracing::debug!("Ignoring local discovery query from {}", from);
+            continue;
+        }
+        let Some(mut info) = addr_info.lock().unwrap().clone() else {
+            continue;
+        };
+        // leave out addresses until the answer is no larger than the query
+        let answer = loop {
+            let answer =
+                Message::Announce(Box::new(Announcement::new(&key, &name(), info.clone())));
+            let answer = postcard::to_allocvec(&answer).expect("postcard serialization failed");
+            if answer.len() <= len || info.direct_addresses.pop_last().is_none() {
+                break answer;
+            }
+        };
+        if answer.len() > len {
+            tracing::debug!("Not answering a query of {} bytes from {}", len, from);
+            continue;
+        }
+        answers += 1;
+        if let Err(e) = socket.send_to(&answer, from).await {
+            tracing::debug!(
+                "Failed to answer local discovery query from {}: {}",
+                from,
+                e
+            );
+        }
+    }
+}
+
+/// A query padded to [QUERY_SIZE]
+fn query_message() -> Vec<u8> {
+    let mut padding = Vec::new();
+    let mut query;
+    loop {
+        query = postcard::to_allocvec(&Message::Query {
+            padding: padding.clone(),
+        })
+        .expect("postcard serialization failed");
+        if query.len() >= QUERY_SIZE {
+            return query;
+        }
+        padding.resize(padding.len() + QUERY_SIZE - query.len(), 0);
+    }
+}
+
+/// Ask the daemons on the local network for their addresses
+///
+/// Calls `f` with every valid announcement until it returns true, or until
+/// `wait` has passed. Daemons answer every query, so the same one may be
+/// seen more than once.
+pub async fn query_local(
+    wait: Duration,
+    mut f: impl FnMut(Announcement) -> bool,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let query = query_message();
+    let target = SocketAddr::V4(LOCAL_DISCOVERY_GROUP);
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    let mut interval = tokio::time::interval(QUERY_INTERVAL);
+    let deadline = tokio::time::sleep(wait);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Ok(()),
+            _ = interval.tick() => {
+                socket
+                    .send_to(&query, target)
+                    .await
+                    .context("sending local discovery query")?;
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (len, from) = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        tracing::debug!("Receiving local discovery answers failed: {}", e);
+                        tokio::time::sleep(RECV_ERROR_DELAY).await;
+                        continue;
+                    }
+                };
+                let Ok(Message::Announce(announcement)) = postcard::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+                if let Err(e) = announcement.verify() {
+                    tracing::debug!("Ignoring announcement from {}: {}", from, e);
+                    continue;
+                }
+                if f(*announcement) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
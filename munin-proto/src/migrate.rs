@@ -0,0 +1,55 @@
+//! Upgrading config files written by older versions, for daemons and controllers.
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Upgrades a config from one version to the next
+pub type Migration = fn(&mut toml::Table) -> anyhow::Result<()>;
+
+/// Parse a config file and bring it to the latest version
+///
+/// `migrations[i]` upgrades version `i` to `i + 1`, so the latest version is
+/// `migrations.len()`. Files without a version are version 0. Returns the
+/// upgraded table, and the version of the file if it had to be upgraded.
+pub fn upgrade_config(
+    data: &str,
+    migrations: &[Migration],
+) -> anyhow::Result<(toml::Table, Option<u32>)> {
+    let mut table: toml::Table = toml::from_str(data)?;
+    let latest = migrations.len() as u32;
+    let version = match table.get("version") {
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .context("version must be a positive integer")?,
+        None => 0,
+    };
+    anyhow::ensure!(
+        version <= latest,
+        "config version {} is newer than the latest known version {}",
+        version,
+        latest
+    );
+    if version == latest {
+        return Ok((table, None));
+    }
+    let secret_key = table.get("secret_key").cloned();
+    for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+        migration(&mut table).with_context(|| format!("migrating config from version {}", from))?;
+    }
+    // the one thing that can't be recreated
+    anyhow::ensure!(
+        table.get("secret_key") == secret_key.as_ref(),
+        "migration changed the secret key"
+    );
+    table.insert("version".to_string(), toml::Value::Integer(latest.into()));
+    Ok((table, Some(version)))
+}
+
+/// Keep a copy of a config file before it is replaced by an upgraded one
+pub fn backup_config(path: &Path, version: u32) -> anyhow::Result<PathBuf> {
+    let backup = path.with_extension(format!("toml.v{}.bak", version));
+    std::fs::copy(path, &backup)
+        .with_context(|| format!("backing up config to {}", backup.display()))?;
+    Ok(backup)
+}
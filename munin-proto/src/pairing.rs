@@ -0,0 +1,44 @@
+//! Enrolling a new controller with a one-time secret, see [crate::Request::Pair].
+use std::{fmt, str::FromStr};
+
+use iroh_base::ticket::{self, Ticket};
+use iroh_net::NodeAddr;
+use serde::{Deserialize, Serialize};
+
+/// Length of a pairing secret in bytes
+pub const PAIRING_SECRET_LEN: usize = 16;
+
+pub type PairingSecret = [u8; PAIRING_SECRET_LEN];
+
+/// How to reach a daemon, plus a one-time secret to prove we are allowed to pair
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PairingTicket {
+    pub node: NodeAddr,
+    pub secret: PairingSecret,
+}
+
+impl Ticket for PairingTicket {
+    const KIND: &'static str = "muninpair";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).expect("postcard serialization failed")
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ticket::Error> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
+
+impl fmt::Display for PairingTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+
+impl FromStr for PairingTicket {
+    type Err = ticket::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ticket::deserialize(s)
+    }
+}
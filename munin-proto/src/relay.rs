@@ -0,0 +1,115 @@
+//! The relay servers used by daemons and controllers.
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use iroh_net::{
+    defaults::DEFAULT_STUN_PORT,
+    relay::{RelayMap, RelayMode, RelayNode, RelayUrl},
+};
+use serde::{Deserialize, Serialize};
+
+/// Which relay servers to use
+///
+/// In config files this is `"default"`, `"disabled"`, the url of a relay
+/// server, or a list of urls.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "TomlRelay", into = "TomlRelay")]
+pub enum RelayConfig {
+    /// The relay servers of number 0
+    #[default]
+    Default,
+    /// Only direct connections, e.g. in a LAN without internet access
+    Disabled,
+    /// Our own relay servers, never empty
+    Custom(BTreeSet<RelayUrl>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum TomlRelay {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RelayConfig {
+    pub fn is_default(&self) -> bool {
+        *self == RelayConfig::Default
+    }
+
+    pub fn mode(&self) -> RelayMode {
+        match self {
+            RelayConfig::Default => RelayMode::Default,
+            RelayConfig::Disabled => RelayMode::Disabled,
+            RelayConfig::Custom(urls) => {
+                let nodes = urls.iter().map(|url| RelayNode {
+                    url: url.clone(),
+                    stun_only: false,
+                    stun_port: DEFAULT_STUN_PORT,
+                });
+                RelayMode::Custom(RelayMap::from_nodes(nodes).expect("urls are unique"))
+            }
+        }
+    }
+}
+
+impl fmt::Display for RelayConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayConfig::Default => write!(f, "default"),
+            RelayConfig::Disabled => write!(f, "disabled"),
+            RelayConfig::Custom(urls) => {
+                let urls = urls.iter().map(|url| url.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", urls.join(","))
+            }
+        }
+    }
+}
+
+/// `default`, `disabled`, or a comma separated list of relay urls
+impl FromStr for RelayConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(RelayConfig::Default),
+            "disabled" => Ok(RelayConfig::Disabled),
+            urls => parse_urls(urls.split(',')),
+        }
+    }
+}
+
+fn parse_urls<'a>(urls: impl IntoIterator<Item = &'a str>) -> Result<RelayConfig, String> {
+    let urls = urls
+        .into_iter()
+        .map(|url| {
+            url.trim()
+                .parse()
+                .map_err(|e| format!("invalid relay url {}: {}", url, e))
+        })
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    if urls.is_empty() {
+        return Err("no relay urls, use \"disabled\" to turn off relays".to_string());
+    }
+    Ok(RelayConfig::Custom(urls))
+}
+
+impl TryFrom<TomlRelay> for RelayConfig {
+    type Error = String;
+
+    fn try_from(value: TomlRelay) -> Result<Self, Self::Error> {
+        match value {
+            TomlRelay::One(value) => value.parse(),
+            TomlRelay::Many(urls) => parse_urls(urls.iter().map(|url| url.as_str())),
+        }
+    }
+}
+
+impl From<RelayConfig> for TomlRelay {
+    fn from(value: RelayConfig) -> Self {
+        match value {
+            RelayConfig::Custom(urls) if urls.len() > 1 => {
+                TomlRelay::Many(urls.iter().map(|url| url.to_string()).collect())
+            }
+            other => TomlRelay::One(other.to_string()),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use munin_proto::{AudioOutput, AudioSource, PlayAudioRequest, Request};
+
+fn discriminant(request: &Request) -> u8 {
+    postcard::to_allocvec(request).unwrap()[0]
+}
+
+#[test]
+fn test_request_discriminants() {
+    // the requests of the first release keep their index, so old and new
+    // controllers and daemons don't mistake one request for another
+    assert_eq!(discriminant(&Request::ListProcesses), 0);
+    assert_eq!(discriminant(&Request::KillProcess(1)), 1);
+    let play = PlayAudioRequest {
+        source: AudioSource::Sound("wake_up".to_string()),
+        volume: None,
+        wait: false,
+        output: AudioOutput::Default,
+    };
+    assert_eq!(discriminant(&Request::PlayAudio(play)), 2);
+    assert_eq!(discriminant(&Request::GetSystemInfo), 3);
+    assert_eq!(discriminant(&Request::Shutdown), 4);
+    // every daemon has to understand the version request
+    assert_eq!(discriminant(&Request::GetVersion), 8);
+}
@@ -1,19 +1,37 @@
 use std::str::FromStr;
 
 const MUNIN_ALLOWED_NODES: &str = "MUNIN_ALLOWED_NODES";
+const MUNIN_OWNER: &str = "MUNIN_OWNER";
 
 fn main() {
     println!("cargo:rerun-if-env-changed={MUNIN_ALLOWED_NODES}");
-    if let Ok(x) = std::env::var(MUNIN_ALLOWED_NODES) {
-        let nodes = x
-            .split(',')
-            .map(iroh_base::key::NodeId::from_str)
-            .collect::<Result<Vec<_>, _>>();
-        if let Err(e) = nodes {
-            eprintln!("{MUNIN_ALLOWED_NODES} is not a valid comma separated list of node ids");
+    println!("cargo:rerun-if-env-changed={MUNIN_OWNER}");
+    if let Ok(x) = std::env::var(MUNIN_OWNER) {
+        if let Err(e) = iroh_base::key::NodeId::from_str(&x) {
+            eprintln!("{MUNIN_OWNER} is not a valid node id");
             eprintln!("  {}", x);
             eprintln!("  {}", e);
             std::process::exit(1);
         }
     }
+    if let Ok(x) = std::env::var(MUNIN_ALLOWED_NODES) {
+        for s in x.split(',') {
+            // an optional role may follow the node id, e.g. <node id>=viewer
+            let (id, role) = s
+                .split_once('=')
+                .map_or((s, None), |(id, role)| (id, Some(role)));
+            if let Err(e) = iroh_base::key::NodeId::from_str(id) {
+                eprintln!("{MUNIN_ALLOWED_NODES} is not a valid comma separated list of node ids");
+                eprintln!("  {}", x);
+                eprintln!("  {}", e);
+                std::process::exit(1);
+            }
+            if let Some(Err(e)) = role.map(munin_proto::Role::from_str) {
+                eprintln!("{MUNIN_ALLOWED_NODES} has an invalid role for {}", id);
+                eprintln!("  {}", x);
+                eprintln!("  {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
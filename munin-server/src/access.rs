@@ -0,0 +1,125 @@
+//! Queue of unknown nodes that have tried to connect, for admins to approve or deny.
+use std::{path::PathBuf, sync::Mutex, time::SystemTime};
+
+use iroh_net::NodeId;
+use munin_proto::AccessRequest;
+
+use crate::config::write_atomic;
+
+/// Self-declared names are cut to this many characters
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug)]
+pub struct AccessRequests {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl AccessRequests {
+    /// Pending requests beyond this are dropped, least recently seen first
+    pub const MAX_PENDING: usize = 100;
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<AccessRequest>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, requests: &[AccessRequest]) -> anyhow::Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec(requests)?)
+    }
+
+    /// Record an attempt by an unknown node, returning its updated request
+    pub fn record(&self, node: NodeId, name: Option<String>) -> anyhow::Result<AccessRequest> {
+        let _guard = self.lock.lock().unwrap();
+        let mut requests = self.load()?;
+        let now = SystemTime::now();
+        let name = name.map(|name| {
+            name.chars()
+                .filter(|c| !c.is_control())
+                .take(MAX_NAME_LEN)
+                .collect::<String>()
+        });
+        let request = match requests.iter_mut().find(|r| r.node == node) {
+            Some(request) => {
+                request.last_seen = now;
+                request.attempts = request.attempts.saturating_add(1);
+                if name.is_some() {
+                    request.name = name;
+                }
+                request.clone()
+            }
+            None => {
+                let request = AccessRequest {
+                    node,
+                    name,
+                    first_seen: now,
+                    last_seen: now,
+                    attempts: 1,
+                    denied: false,
+                };
+                requests.push(request.clone());
+                request
+            }
+        };
+        let pending = requests.iter().filter(|r| !r.denied).count();
+        if pending > Self::MAX_PENDING {
+            let oldest = requests
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| !r.denied)
+                .min_by_key(|(_, r)| r.last_seen)
+                .map(|(i, _)| i);
+            if let Some(i) = oldest {
+                requests.remove(i);
+            }
+        }
+        self.save(&requests)?;
+        Ok(request)
+    }
+
+    /// Whether the node has a request in the queue, pending or denied
+    pub fn contains(&self, node: NodeId) -> anyhow::Result<bool> {
+        Ok(self.list()?.iter().any(|r| r.node == node))
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<AccessRequest>> {
+        let _guard = self.lock.lock().unwrap();
+        self.load()
+    }
+
+    /// Remove the request of a node, e.g. after it was approved
+    pub fn remove(&self, node: NodeId) -> anyhow::Result<AccessRequest> {
+        let _guard = self.lock.lock().unwrap();
+        let mut requests = self.load()?;
+        let index = requests
+            .iter()
+            .position(|r| r.node == node)
+            .ok_or_else(|| anyhow::anyhow!("no access request from {}", node))?;
+        let request = requests.remove(index);
+        self.save(&requests)?;
+        Ok(request)
+    }
+
+    /// Mark the request of a node as denied, so it is no longer pending
+    pub fn deny(&self, node: NodeId) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut requests = self.load()?;
+        let request = requests
+            .iter_mut()
+            .find(|r| r.node == node)
+            .ok_or_else(|| anyhow::anyhow!("no access request from {}", node))?;
+        request.denied = true;
+        self.save(&requests)?;
+        Ok(())
+    }
+}
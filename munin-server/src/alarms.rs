@@ -0,0 +1,68 @@
+//! Firing alarms stored in the config, without any controller connected.
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Datelike, Local, TimeDelta, Timelike};
+use munin_proto::{AlarmSpec, PlayAudioRequest};
+
+use crate::{audio::MAX_VOLUME, play_audio, AudioPlayer, Config, SoundCatalog};
+
+/// Check that an alarm can be played before storing it
+pub fn validate(catalog: &SoundCatalog, spec: &AlarmSpec) -> anyhow::Result<()> {
+    anyhow::ensure!(!spec.days.is_empty(), "alarm has no days");
+    if let Some(volume) = spec.volume {
+        anyhow::ensure!(
+            (0.0..=MAX_VOLUME).contains(&volume),
+            "volume must be between 0 and {}",
+            MAX_VOLUME
+        );
+    }
+    catalog.resolve(&spec.source)?;
+    Ok(())
+}
+
+/// Is the alarm due in the minute starting at `time`?
+pub fn is_due(spec: &AlarmSpec, time: &DateTime<Local>) -> bool {
+    spec.at.hour as u32 == time.hour()
+        && spec.at.minute as u32 == time.minute()
+        && spec.days.contains(time.weekday().num_days_from_monday())
+}
+
+/// The start of the minute after `time`
+fn next_minute(time: DateTime<Local>) -> DateTime<Local> {
+    let start = time
+        .with_second(0)
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or(time);
+    start + TimeDelta::minutes(1)
+}
+
+/// Fire due alarms at the start of every minute, forever
+pub async fn run(config: Arc<Mutex<Config>>, catalog: Arc<SoundCatalog>, player: AudioPlayer) {
+    let mut minute = next_minute(Local::now());
+    loop {
+        let wait = (minute - Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        let due = config
+            .lock()
+            .unwrap()
+            .alarms
+            .iter()
+            .filter(|alarm| is_due(&alarm.spec, &minute))
+            .cloned()
+            .collect::<Vec<_>>();
+        for alarm in due {
+            tracing::info!("Firing alarm {}: {}", alarm.id, alarm.spec);
+            let request = PlayAudioRequest {
+                source: alarm.spec.source,
+                volume: alarm.spec.volume,
+                wait: false,
+                output: alarm.spec.output,
+            };
+            if let Err(e) = play_audio(&catalog, &player, request).await {
+                tracing::warn!("Alarm {} failed: {}", alarm.id, e);
+            }
+        }
+        // skip minutes that have passed while the machine was asleep
+        minute = next_minute(minute.max(Local::now()));
+    }
+}
@@ -0,0 +1,503 @@
+//! Audio playback on a dedicated thread.
+//!
+//! rodio output streams are not `Send` and waiting for a clip to finish blocks,
+//! so all playback happens on a single thread that owns the output streams. The
+//! async request handlers talk to it through an [AudioPlayer] handle.
+//!
+//! Where the decoded clips go is up to an [AudioBackend]. [RodioBackend] plays
+//! on the sound devices of the machine, [NullBackend] only decodes the clips and
+//! records what it would have played, so the audio path can be tested on
+//! machines without a sound card.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use cpal::traits::{DeviceTrait, HostTrait};
+use munin_proto::{AudioDevice, AudioOutput, PlaybackStatus};
+use rodio::{source::Amplify, Decoder, Source};
+use tokio::sync::oneshot;
+
+/// Amplification used when a request does not specify a volume
+///
+/// The embedded clips are quite quiet, so this is well above 1.0.
+pub const DEFAULT_VOLUME: f32 = 10.0;
+
+/// Maximum amplification we accept in a request
+pub const MAX_VOLUME: f32 = 20.0;
+
+/// How often the audio thread checks if it can close an idle output stream
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The output side of the audio thread
+///
+/// The backend is created on the audio thread and never leaves it, so it does
+/// not need to be `Send`.
+pub trait AudioBackend {
+    /// List the output devices
+    fn devices(&mut self) -> anyhow::Result<Vec<AudioDevice>>;
+
+    /// Queue a clip on the device with the given name, after the clips already
+    /// queued on that device
+    fn append(&mut self, device: &str, clip: Clip) -> anyhow::Result<()>;
+
+    /// Stop the current clips on all devices and drop everything that is queued
+    fn stop(&mut self);
+
+    /// Release devices that have nothing left to play
+    fn release_idle(&mut self) {}
+}
+
+enum Command {
+    Play {
+        data: Bytes,
+        volume: f32,
+        output: AudioOutput,
+        queued: oneshot::Sender<anyhow::Result<Vec<DeviceQueued>>>,
+    },
+    Stop,
+    ListDevices(oneshot::Sender<anyhow::Result<Vec<AudioDevice>>>),
+}
+
+/// Result of queueing a clip on a single device
+struct DeviceQueued {
+    device: String,
+    result: anyhow::Result<oneshot::Receiver<PlaybackStatus>>,
+}
+
+/// Handle to the audio thread
+#[derive(Debug, Clone)]
+pub struct AudioPlayer {
+    send: mpsc::Sender<Command>,
+}
+
+/// A clip that was queued for playback
+#[derive(Debug)]
+pub struct Playback {
+    done: oneshot::Receiver<PlaybackStatus>,
+}
+
+impl Playback {
+    /// Wait until the clip has finished playing or was stopped
+    pub async fn finished(self) -> PlaybackStatus {
+        // if the clip is dropped without reporting, it did not finish
+        self.done.await.unwrap_or(PlaybackStatus::Stopped)
+    }
+}
+
+impl AudioPlayer {
+    /// Spawn the audio thread, playing on the sound devices of the machine
+    ///
+    /// The thread exits when the last handle is dropped.
+    pub fn spawn() -> anyhow::Result<Self> {
+        Self::spawn_with(RodioBackend::default)
+    }
+
+    /// Spawn the audio thread with a custom backend
+    ///
+    /// `make_backend` is called on the audio thread.
+    pub fn spawn_with<B, F>(make_backend: F) -> anyhow::Result<Self>
+    where
+        B: AudioBackend,
+        F: FnOnce() -> B + Send + 'static,
+    {
+        let (send, recv) = mpsc::channel();
+        thread::Builder::new()
+            .name("munin-audio".into())
+            .spawn(move || audio_thread(make_backend(), recv))?;
+        Ok(Self { send })
+    }
+
+    /// Queue a clip for playback on one or more output devices
+    ///
+    /// Returns once the clip is decoded and queued, so errors are reported early.
+    /// There is one result per device. Use [Playback::finished] to wait for the
+    /// end of the clip.
+    pub async fn play(
+        &self,
+        data: Bytes,
+        volume: f32,
+        output: AudioOutput,
+    ) -> anyhow::Result<Vec<(String, anyhow::Result<Playback>)>> {
+        anyhow::ensure!(
+            (0.0..=MAX_VOLUME).contains(&volume),
+            "volume must be between 0 and {}",
+            MAX_VOLUME
+        );
+        let (queued, queued_recv) = oneshot::channel();
+        self.send
+            .send(Command::Play {
+                data,
+                volume,
+                output,
+                queued,
+            })
+            .map_err(|_| anyhow::anyhow!("audio thread is gone"))?;
+        let queued = queued_recv
+            .await
+            .map_err(|_| anyhow::anyhow!("audio thread is gone"))??;
+        Ok(queued
+            .into_iter()
+            .map(|q| (q.device, q.result.map(|done| Playback { done })))
+            .collect())
+    }
+
+    /// List the output devices
+    pub async fn devices(&self) -> anyhow::Result<Vec<AudioDevice>> {
+        let (reply, reply_recv) = oneshot::channel();
+        self.send
+            .send(Command::ListDevices(reply))
+            .map_err(|_| anyhow::anyhow!("audio thread is gone"))?;
+        reply_recv
+            .await
+            .map_err(|_| anyhow::anyhow!("audio thread is gone"))?
+    }
+
+    /// Stop the current clips on all devices and drop everything that is queued
+    pub fn stop(&self) -> anyhow::Result<()> {
+        self.send
+            .send(Command::Stop)
+            .map_err(|_| anyhow::anyhow!("audio thread is gone"))
+    }
+}
+
+fn audio_thread(mut backend: impl AudioBackend, recv: mpsc::Receiver<Command>) {
+    loop {
+        let command = match recv.recv_timeout(IDLE_CHECK_INTERVAL) {
+            Ok(command) => command,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                backend.release_idle();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        match command {
+            Command::Play {
+                data,
+                volume,
+                output,
+                queued,
+            } => {
+                let res = backend
+                    .devices()
+                    .and_then(|devices| select_devices(devices, &output))
+                    .map(|devices| {
+                        devices
+                            .into_iter()
+                            .map(|device| {
+                                let result = enqueue(&mut backend, &device, data.clone(), volume);
+                                DeviceQueued { device, result }
+                            })
+                            .collect()
+                    });
+                queued.send(res).ok();
+            }
+            Command::Stop => backend.stop(),
+            Command::ListDevices(reply) => {
+                reply.send(backend.devices()).ok();
+            }
+        }
+    }
+}
+
+/// Pick the names of the devices to play on for the given output
+fn select_devices(devices: Vec<AudioDevice>, output: &AudioOutput) -> anyhow::Result<Vec<String>> {
+    anyhow::ensure!(!devices.is_empty(), "no audio output devices found");
+    let names = match output {
+        AudioOutput::Default => {
+            let device = devices
+                .into_iter()
+                .find(|d| d.default)
+                .context("no default output device")?;
+            vec![device.name]
+        }
+        AudioOutput::Device(name) => {
+            let exact = devices.iter().find(|d| &d.name == name);
+            let loose = || devices.iter().find(|d| d.name.eq_ignore_ascii_case(name));
+            let device = exact
+                .or_else(loose)
+                .with_context(|| format!("no output device named {}", name))?;
+            vec![device.name.clone()]
+        }
+        AudioOutput::All => devices.into_iter().map(|d| d.name).collect(),
+    };
+    Ok(names)
+}
+
+fn enqueue(
+    backend: &mut impl AudioBackend,
+    device: &str,
+    data: Bytes,
+    volume: f32,
+) -> anyhow::Result<oneshot::Receiver<PlaybackStatus>> {
+    let (done, done_recv) = oneshot::channel();
+    let clip = Clip::decode(data, volume, done)?;
+    backend.append(device, clip)?;
+    Ok(done_recv)
+}
+
+/// A decoded and amplified clip
+///
+/// Reports whether it played to the end or was dropped before.
+pub struct Clip {
+    source: Amplify<Decoder<io::Cursor<Bytes>>>,
+    volume: f32,
+    done: Option<oneshot::Sender<PlaybackStatus>>,
+}
+
+impl Clip {
+    fn decode(
+        data: Bytes,
+        volume: f32,
+        done: oneshot::Sender<PlaybackStatus>,
+    ) -> anyhow::Result<Self> {
+        let source = Decoder::new(io::Cursor::new(data)).context("decoding audio")?;
+        Ok(Self {
+            source: source.amplify(volume),
+            volume,
+            done: Some(done),
+        })
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+}
+
+impl Iterator for Clip {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.source.next();
+        if item.is_none() {
+            if let Some(done) = self.done.take() {
+                done.send(PlaybackStatus::Finished).ok();
+            }
+        }
+        item
+    }
+}
+
+impl Source for Clip {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl Drop for Clip {
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            done.send(PlaybackStatus::Stopped).ok();
+        }
+    }
+}
+
+/// An open output device
+struct OpenDevice {
+    // the stream must be kept alive as long as the sink is playing
+    _stream: rodio::OutputStream,
+    sink: rodio::Sink,
+}
+
+/// Plays on the sound devices of the machine
+#[derive(Default)]
+pub struct RodioBackend {
+    open: BTreeMap<String, OpenDevice>,
+}
+
+fn device_name(device: &cpal::Device) -> String {
+    device
+        .name()
+        .unwrap_or_else(|_| "Unknown Device".to_string())
+}
+
+/// Make device names unique, later devices with a taken name get a number, e.g. "Speakers #2"
+///
+/// Devices can share a name, or all fail to report one and become "Unknown Device".
+pub fn unique_device_names(names: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut taken = BTreeSet::new();
+    names
+        .into_iter()
+        .map(|base| {
+            let mut name = base.clone();
+            let mut n = 1;
+            while !taken.insert(name.clone()) {
+                n += 1;
+                name = format!("{} #{}", base, n);
+            }
+            name
+        })
+        .collect()
+}
+
+/// The output devices of the host, by unique name
+fn output_devices(host: &cpal::Host) -> anyhow::Result<Vec<(String, cpal::Device)>> {
+    let devices = host.output_devices()?.collect::<Vec<_>>();
+    let names = unique_device_names(devices.iter().map(device_name));
+    Ok(names.into_iter().zip(devices).collect())
+}
+
+impl AudioBackend for RodioBackend {
+    fn devices(&mut self) -> anyhow::Result<Vec<AudioDevice>> {
+        let host = cpal::default_host();
+        // the default device is not told apart from others with the same name
+        let default = host.default_output_device().map(|d| device_name(&d));
+        let devices = output_devices(&host)?
+            .into_iter()
+            .map(|(name, _)| {
+                let default = default.as_ref() == Some(&name);
+                AudioDevice { name, default }
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    fn append(&mut self, name: &str, clip: Clip) -> anyhow::Result<()> {
+        if !self.open.contains_key(name) {
+            let (_, device) = output_devices(&cpal::default_host())?
+                .into_iter()
+                .find(|(device, _)| device == name)
+                .with_context(|| format!("no output device named {}", name))?;
+            let (stream, handle) = rodio::OutputStream::try_from_device(&device)
+                .with_context(|| format!("failed to create stream for device {}", name))?;
+            let sink = rodio::Sink::try_new(&handle)?;
+            let device = OpenDevice {
+                _stream: stream,
+                sink,
+            };
+            self.open.insert(name.to_string(), device);
+        }
+        self.open[name].sink.append(clip);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // dropping the sinks and the streams drops all clips, which
+        // reports them as stopped
+        for (_, device) in std::mem::take(&mut self.open) {
+            device.sink.stop();
+        }
+    }
+
+    fn release_idle(&mut self) {
+        self.open.retain(|_, device| !device.sink.empty());
+    }
+}
+
+/// What [NullBackend] did with a clip
+#[derive(Debug, Clone)]
+pub struct PlayedClip {
+    pub device: String,
+    pub volume: f32,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Number of samples, over all channels
+    pub samples: u64,
+    pub duration: Duration,
+    /// Largest absolute sample value, from 0.0 to 1.0
+    pub peak: f32,
+}
+
+/// Decodes clips completely without playing them
+///
+/// Clips are decoded as soon as they are appended, so they finish in the order
+/// they were queued. Clones share the record of played clips.
+#[derive(Debug, Clone)]
+pub struct NullBackend {
+    devices: Vec<String>,
+    played: Arc<Mutex<Vec<PlayedClip>>>,
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        Self::with_devices(["null"])
+    }
+}
+
+impl NullBackend {
+    /// A backend with the given device names, the first one is the default
+    pub fn with_devices<I, S>(devices: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            devices: devices.into_iter().map(Into::into).collect(),
+            played: Default::default(),
+        }
+    }
+
+    /// The clips that were played so far, in order
+    pub fn played(&self) -> Vec<PlayedClip> {
+        self.played.lock().unwrap().clone()
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn devices(&mut self) -> anyhow::Result<Vec<AudioDevice>> {
+        Ok(self
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(i, name)| AudioDevice {
+                name: name.clone(),
+                default: i == 0,
+            })
+            .collect())
+    }
+
+    fn append(&mut self, device: &str, mut clip: Clip) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.devices.iter().any(|d| d == device),
+            "no output device named {}",
+            device
+        );
+        let channels = clip.channels();
+        let sample_rate = clip.sample_rate();
+        let mut samples = 0u64;
+        let mut peak = 0f32;
+        for sample in clip.by_ref() {
+            samples += 1;
+            peak = peak.max((f32::from(sample) / 32768.0).abs());
+        }
+        let frames = samples / u64::from(channels.max(1));
+        let duration = Duration::from_secs_f64(frames as f64 / f64::from(sample_rate.max(1)));
+        tracing::info!(
+            "Decoded {} samples ({:?}) for device {}",
+            samples,
+            duration,
+            device
+        );
+        self.played.lock().unwrap().push(PlayedClip {
+            device: device.to_string(),
+            volume: clip.volume(),
+            channels,
+            sample_rate,
+            samples,
+            duration,
+            peak,
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // clips are done as soon as they are queued, so there is nothing to stop
+    }
+}
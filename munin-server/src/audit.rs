@@ -0,0 +1,196 @@
+//! Append only log of all requests, as json lines in the daemon data dir.
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use munin_proto::{AuditEntry, AuditOutcome, AuditQuery, Error, MAX_RESPONSE_SIZE};
+use tokio::sync::oneshot;
+
+/// Once the log is larger than this, it is moved aside and a new one is started
+pub const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// Upper bound for [AuditQuery::limit]
+pub const MAX_QUERY_ENTRIES: usize = 1000;
+/// Upper bound for the encoded entries of a query, with room for the rest of the response
+const MAX_QUERY_BYTES: usize = MAX_RESPONSE_SIZE / 2;
+/// Longer request descriptions and error messages are cut off when recorded,
+/// requests of unknown nodes can be up to [munin_proto::MAX_REQUEST_SIZE]
+const MAX_FIELD_LEN: usize = 256;
+
+/// Handle to the audit log thread
+///
+/// Appends, rotation and queries all touch the file system, so they happen on
+/// a dedicated thread instead of blocking the async request handlers. The
+/// thread handles them in order, so a query sees everything recorded before it.
+#[derive(Debug)]
+pub struct AuditLog {
+    send: mpsc::Sender<Command>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Record(AuditEntry),
+    Flush(oneshot::Sender<()>),
+    Query(AuditQuery, oneshot::Sender<anyhow::Result<Vec<AuditEntry>>>),
+}
+
+impl AuditLog {
+    /// Spawn the audit log thread, it exits when the handle is dropped
+    pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        Self::with_max_size(path, MAX_LOG_SIZE)
+    }
+
+    pub fn with_max_size(path: impl Into<PathBuf>, max_size: u64) -> anyhow::Result<Self> {
+        let files = LogFiles {
+            path: path.into(),
+            max_size,
+        };
+        let (send, recv) = mpsc::channel();
+        thread::Builder::new()
+            .name("munin-audit".into())
+            .spawn(move || audit_thread(files, recv))?;
+        Ok(Self { send })
+    }
+
+    /// Queue an entry to be appended, logging instead of failing since
+    /// auditing must not stop the daemon from working
+    pub fn record(&self, entry: &AuditEntry) {
+        if self.send.send(Command::Record(truncated(entry))).is_err() {
+            tracing::warn!("Failed to write audit log: audit thread is gone");
+        }
+    }
+
+    /// Wait until the entries recorded so far are written
+    pub async fn flush(&self) {
+        let (done, done_recv) = oneshot::channel();
+        if self.send.send(Command::Flush(done)).is_ok() {
+            done_recv.await.ok();
+        }
+    }
+
+    /// The most recent entries matching the query, oldest first
+    pub async fn query(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let (reply, reply_recv) = oneshot::channel();
+        self.send
+            .send(Command::Query(query.clone(), reply))
+            .map_err(|_| anyhow::anyhow!("audit thread is gone"))?;
+        reply_recv
+            .await
+            .map_err(|_| anyhow::anyhow!("audit thread is gone"))?
+    }
+}
+
+fn audit_thread(files: LogFiles, recv: mpsc::Receiver<Command>) {
+    for command in recv {
+        match command {
+            Command::Record(entry) => {
+                if let Err(e) = files.append(&entry) {
+                    tracing::warn!("Failed to write audit log: {}", e);
+                }
+            }
+            Command::Query(query, reply) => {
+                reply.send(files.query(&query)).ok();
+            }
+            Command::Flush(done) => {
+                done.send(()).ok();
+            }
+        }
+    }
+}
+
+/// The log and its rotated predecessor, only used on the audit log thread
+#[derive(Debug)]
+struct LogFiles {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl LogFiles {
+    /// The previous log, the only one kept after rotation
+    fn rotated_path(&self) -> PathBuf {
+        self.path.with_extension("1.jsonl")
+    }
+
+    fn append(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size >= self.max_size {
+            std::fs::rename(&self.path, self.rotated_path())?;
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    fn query(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for path in [self.rotated_path(), self.path.clone()] {
+            read_entries(&path, query, &mut entries)?;
+        }
+        let limit = query
+            .limit
+            .map_or(MAX_QUERY_ENTRIES, |limit| limit as usize)
+            .min(MAX_QUERY_ENTRIES);
+        let mut skip = entries.len().saturating_sub(limit);
+        // keep the most recent entries that fit into a response
+        let mut bytes = 0;
+        for (i, entry) in entries.iter().enumerate().skip(skip).rev() {
+            bytes += postcard::to_allocvec(entry)?.len();
+            if bytes > MAX_QUERY_BYTES {
+                skip = i + 1;
+                break;
+            }
+        }
+        Ok(entries.split_off(skip))
+    }
+}
+
+/// The entry with its free text fields cut off at [MAX_FIELD_LEN]
+fn truncated(entry: &AuditEntry) -> AuditEntry {
+    let mut entry = entry.clone();
+    truncate(&mut entry.request);
+    if let AuditOutcome::Error(error) = &mut entry.outcome {
+        match error {
+            Error::Failed(message) | Error::InvalidDelegation(message) => truncate(message),
+            Error::ProtectedProcess { name, .. } => truncate(name),
+            _ => {}
+        }
+    }
+    entry
+}
+
+fn truncate(text: &mut String) {
+    if let Some((end, _)) = text.char_indices().nth(MAX_FIELD_LEN) {
+        text.truncate(end);
+        text.push_str("...");
+    }
+}
+
+fn read_entries(
+    path: &Path,
+    query: &AuditQuery,
+    entries: &mut Vec<AuditEntry>,
+) -> anyhow::Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for line in BufReader::new(file).lines() {
+        // skip lines we can't parse, e.g. a partial line after a crash
+        let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) else {
+            continue;
+        };
+        if query.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(())
+}
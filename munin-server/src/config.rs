@@ -1,21 +1,235 @@
-use std::{collections::BTreeSet, path::PathBuf, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use iroh_net::NodeId;
+use munin_proto::{Alarm, AlarmSpec, DelegationChain, Grant, Migration, Role};
 use serde::{Deserialize, Serialize};
 
+use crate::{Limits, Network, Timeouts};
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    /// Shown to controllers, in local discovery, the version and the system info
     pub name: String,
     pub secret_key: iroh_net::key::SecretKey,
-    pub allowed_nodes: BTreeSet<NodeId>,
+    /// The controllers that may connect, and what they may do
+    pub allowed_nodes: BTreeMap<NodeId, Grant>,
+    /// Key that is an admin, and can delegate access to other controllers
+    pub owner: Option<NodeId>,
+    pub alarms: Vec<Alarm>,
+    /// Id of the next alarm, ids are never reused
+    pub next_alarm_id: u32,
+    /// Process names that can't be killed, on top of [crate::BUILTIN_PROTECTED]
+    pub protected_processes: Vec<String>,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub network: Network,
+}
+
+impl PartialEq for Config {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.secret_key.to_bytes() == other.secret_key.to_bytes()
+            && self.allowed_nodes == other.allowed_nodes
+            && self.owner == other.owner
+            && self.alarms == other.alarms
+            && self.next_alarm_id == other.next_alarm_id
+            && self.protected_processes == other.protected_processes
+            && self.limits == other.limits
+            && self.timeouts == other.timeouts
+            && self.network == other.network
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TomlConfig {
+    /// See [MIGRATIONS]
+    #[serde(default)]
+    version: u32,
+    #[serde(default = "default_name")]
     name: String,
     secret_key: String,
-    allowed_nodes: Vec<String>,
+    #[serde(default)]
+    allowed_nodes: BTreeMap<String, TomlGrant>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default)]
+    alarms: Vec<TomlAlarm>,
+    #[serde(default = "default_next_alarm_id")]
+    next_alarm_id: u32,
+    #[serde(default)]
+    protected_processes: Vec<String>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    network: Network,
+}
+
+fn default_name() -> String {
+    "munin-daemon".to_string()
+}
+
+fn default_next_alarm_id() -> u32 {
+    1
+}
+
+/// Longer names would not fit into a local discovery announcement
+const MAX_NAME_LEN: usize = 64;
+
+/// Whether a grant is an admin that [Config::check_admins] counts
+fn is_permanent_admin(grant: Option<&Grant>) -> bool {
+    grant.is_some_and(|grant| grant.role == Role::Admin && grant.is_permanent())
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(!name.trim().is_empty(), "the name must not be empty");
+    anyhow::ensure!(
+        name.chars().count() <= MAX_NAME_LEN,
+        "the name must not be longer than {} characters",
+        MAX_NAME_LEN
+    );
+    anyhow::ensure!(
+        !name.chars().any(|c| c.is_control()),
+        "the name must not contain control characters"
+    );
+    Ok(())
+}
+
+/// Upgrades of the config file, the version of the file is the number of
+/// migrations applied to it
+///
+/// New fields with a serde default don't need a migration, only changes to
+/// existing fields do.
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: allowed nodes were a plain list of node ids before there were roles
+    |config| {
+        if let Some(toml::Value::Array(ids)) = config.get("allowed_nodes") {
+            let grants = ids
+                .iter()
+                .map(|id| {
+                    let id = id.as_str().context("allowed node must be a string")?;
+                    Ok((id.to_string(), toml::Value::from("admin")))
+                })
+                .collect::<anyhow::Result<toml::Table>>()?;
+            config.insert("allowed_nodes".to_string(), grants.into());
+        }
+        Ok(())
+    },
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum TomlGrant {
+    /// Just the role, for permanent grants
+    Role(Role),
+    Limited {
+        role: Role,
+        /// RFC 3339 timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        requests: Option<Vec<String>>,
+    },
+}
+
+impl TryFrom<TomlGrant> for Grant {
+    type Error = anyhow::Error;
+
+    fn try_from(value: TomlGrant) -> Result<Self, Self::Error> {
+        Ok(match value {
+            TomlGrant::Role(role) => Grant::from(role),
+            TomlGrant::Limited {
+                role,
+                expires,
+                requests,
+            } => Grant {
+                role,
+                expires: expires
+                    .map(|expires| {
+                        chrono::DateTime::parse_from_rfc3339(&expires).map(SystemTime::from)
+                    })
+                    .transpose()?,
+                requests: requests
+                    .map(|requests| {
+                        requests
+                            .iter()
+                            .map(|kind| kind.parse().map_err(|e: String| anyhow!(e)))
+                            .collect::<anyhow::Result<_>>()
+                    })
+                    .transpose()?,
+            },
+        })
+    }
+}
+
+impl From<Grant> for TomlGrant {
+    fn from(value: Grant) -> Self {
+        if value.is_permanent() {
+            return TomlGrant::Role(value.role);
+        }
+        TomlGrant::Limited {
+            role: value.role,
+            expires: value.expires.map(|expires| {
+                chrono::DateTime::<chrono::Utc>::from(expires)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            }),
+            requests: value
+                .requests
+                .map(|requests| requests.iter().map(|kind| kind.to_string()).collect()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TomlAlarm {
+    id: u32,
+    at: String,
+    days: String,
+    source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume: Option<f32>,
+    #[serde(default = "default_output")]
+    output: String,
+}
+
+fn default_output() -> String {
+    "default".to_string()
+}
+
+impl TryFrom<TomlAlarm> for Alarm {
+    type Error = anyhow::Error;
+
+    fn try_from(value: TomlAlarm) -> Result<Self, Self::Error> {
+        let spec = AlarmSpec {
+            at: value.at.parse().map_err(|e: String| anyhow!(e))?,
+            days: value.days.parse().map_err(|e: String| anyhow!(e))?,
+            source: value.source.parse().map_err(|e: String| anyhow!(e))?,
+            volume: value.volume,
+            output: value.output.parse().map_err(|e: String| anyhow!(e))?,
+        };
+        Ok(Alarm { id: value.id, spec })
+    }
+}
+
+impl From<Alarm> for TomlAlarm {
+    fn from(value: Alarm) -> Self {
+        TomlAlarm {
+            id: value.id,
+            at: value.spec.at.to_string(),
+            days: value.spec.days.to_string(),
+            source: value.spec.source.to_string(),
+            volume: value.spec.volume,
+            output: value.spec.output.to_string(),
+        }
+    }
 }
 
 impl TryFrom<TomlConfig> for Config {
@@ -26,12 +240,38 @@ impl TryFrom<TomlConfig> for Config {
         let allowed_nodes = value
             .allowed_nodes
             .into_iter()
-            .map(|s| NodeId::from_str(&s))
-            .collect::<Result<_, _>>()?;
+            .map(|(id, grant)| Ok((NodeId::from_str(&id)?, grant.try_into()?)))
+            .collect::<anyhow::Result<_>>()?;
+        let alarms = value
+            .alarms
+            .into_iter()
+            .map(Alarm::try_from)
+            .collect::<Result<Vec<Alarm>, _>>()?;
+        // configs from before the counter continue after their highest id
+        let next_alarm_id = alarms
+            .iter()
+            .map(|alarm| {
+                alarm
+                    .id
+                    .checked_add(1)
+                    .with_context(|| format!("alarm id {} is too large", alarm.id))
+            })
+            .try_fold(value.next_alarm_id, |next, id| anyhow::Ok(next.max(id?)))?;
+        let owner = value
+            .owner
+            .map(|owner| NodeId::from_str(&owner))
+            .transpose()?;
         Ok(Config {
             name: value.name,
             secret_key,
             allowed_nodes,
+            owner,
+            alarms,
+            next_alarm_id,
+            protected_processes: value.protected_processes,
+            limits: value.limits,
+            timeouts: value.timeouts,
+            network: value.network,
         })
     }
 }
@@ -39,17 +279,44 @@ impl TryFrom<TomlConfig> for Config {
 impl From<Config> for TomlConfig {
     fn from(value: Config) -> Self {
         TomlConfig {
+            version: MIGRATIONS.len() as u32,
             name: value.name,
             secret_key: value.secret_key.to_string(),
             allowed_nodes: value
                 .allowed_nodes
                 .into_iter()
-                .map(|id| id.to_string())
+                .map(|(id, grant)| (id.to_string(), grant.into()))
                 .collect(),
+            owner: value.owner.map(|owner| owner.to_string()),
+            alarms: value.alarms.into_iter().map(TomlAlarm::from).collect(),
+            next_alarm_id: value.next_alarm_id,
+            protected_processes: value.protected_processes,
+            limits: value.limits,
+            timeouts: value.timeouts,
+            network: value.network,
         }
     }
 }
 
+/// Replace the file in one step, so a reader never sees half of it
+///
+/// Only the owner can read the file, the daemon files hold keys and secrets.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
 fn munin_data_root() -> anyhow::Result<PathBuf> {
     const MUNIN_DIR: &str = "munin-daemon";
     let path = if let Some(val) = std::env::var_os("MUNIN_DATA_DIR") {
@@ -69,14 +336,163 @@ fn munin_data_root() -> anyhow::Result<PathBuf> {
 }
 
 impl Config {
-    pub fn initial_allowed_nodes() -> anyhow::Result<BTreeSet<NodeId>> {
+    /// A config with defaults and no allowed nodes
+    pub fn new(secret_key: iroh_net::key::SecretKey) -> Self {
+        Self {
+            name: default_name(),
+            secret_key,
+            allowed_nodes: BTreeMap::new(),
+            owner: None,
+            alarms: Vec::new(),
+            next_alarm_id: default_next_alarm_id(),
+            protected_processes: Vec::new(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            network: Network::default(),
+        }
+    }
+
+    /// Allowed nodes from `MUNIN_ALLOWED_NODES`, a comma separated list of
+    /// `<node id>` or `<node id>=<role>`. Nodes without a role are admins.
+    pub fn initial_allowed_nodes() -> anyhow::Result<BTreeMap<NodeId, Grant>> {
         match std::env::var("MUNIN_ALLOWED_NODES") {
             Ok(val) => val
                 .split(',')
-                .map(|s| Ok(NodeId::from_str(s)?))
+                .map(|s| {
+                    let (id, role) = match s.split_once('=') {
+                        Some((id, role)) => (id, role.parse().map_err(|e: String| anyhow!(e))?),
+                        None => (s, Role::Admin),
+                    };
+                    Ok((NodeId::from_str(id)?, Grant::from(role)))
+                })
                 .collect::<Result<_, _>>(),
-            Err(_) => Ok(BTreeSet::new()),
+            Err(_) => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Owner from `MUNIN_OWNER`, if set
+    pub fn initial_owner() -> anyhow::Result<Option<NodeId>> {
+        match std::env::var("MUNIN_OWNER") {
+            Ok(val) => Ok(Some(NodeId::from_str(&val)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The grant of a node, either directly or through a delegation from the owner
+    ///
+    /// A direct grant takes precedence over the delegation.
+    pub fn grant_for(
+        &self,
+        node: NodeId,
+        delegation: Option<&DelegationChain>,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<Grant>> {
+        if let Some(grant) = self.allowed_nodes.get(&node) {
+            return Ok(Some(grant.clone()));
+        }
+        if self.owner == Some(node) {
+            return Ok(Some(Role::Admin.into()));
+        }
+        match (self.owner, delegation) {
+            (Some(owner), Some(chain)) => Ok(Some(chain.verify(owner, node, now)?)),
+            (None, Some(_)) => Err(anyhow!("this daemon has no owner")),
+            (_, None) => Ok(None),
+        }
+    }
+
+    /// Allow a node, or replace its grant
+    ///
+    /// Fails if the grant has already expired, or if this would demote the
+    /// last admin. A daemon without any admin can still enroll nodes.
+    pub fn allow_node(&mut self, node: NodeId, grant: impl Into<Grant>) -> anyhow::Result<()> {
+        let grant = grant.into();
+        anyhow::ensure!(
+            !grant.is_expired(SystemTime::now()),
+            "the grant has already expired"
+        );
+        let previous = self.allowed_nodes.insert(node, grant);
+        if is_permanent_admin(previous.as_ref()) {
+            if let Err(e) = self.check_admins() {
+                self.allowed_nodes
+                    .insert(node, previous.expect("was an admin"));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename the daemon
+    ///
+    /// Fails if the name is empty, too long or contains control characters.
+    pub fn set_name(&mut self, name: String) -> anyhow::Result<()> {
+        check_name(&name)?;
+        self.name = name;
+        Ok(())
+    }
+
+    /// Store an alarm with a fresh id and return the id
+    ///
+    /// Ids are never reused, so removing a stale id can't hit a newer alarm.
+    pub fn add_alarm(&mut self, spec: AlarmSpec) -> anyhow::Result<u32> {
+        let id = self.next_alarm_id;
+        self.next_alarm_id = id.checked_add(1).context("no alarm ids left")?;
+        self.alarms.push(Alarm { id, spec });
+        Ok(id)
+    }
+
+    /// Remove an alarm by its id
+    pub fn remove_alarm(&mut self, id: u32) -> anyhow::Result<()> {
+        let len = self.alarms.len();
+        self.alarms.retain(|alarm| alarm.id != id);
+        anyhow::ensure!(self.alarms.len() < len, "no alarm with id {}", id);
+        Ok(())
+    }
+
+    /// Remove a node from the allowed nodes
+    ///
+    /// Fails if the node is not allowed, or if it is the last admin.
+    pub fn disallow_node(&mut self, node: NodeId) -> anyhow::Result<()> {
+        let grant = self
+            .allowed_nodes
+            .remove(&node)
+            .ok_or_else(|| anyhow!("{} is not an allowed node", node))?;
+        if is_permanent_admin(Some(&grant)) {
+            if let Err(e) = self.check_admins() {
+                self.allowed_nodes.insert(node, grant);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all grants that have expired at `now`, returning their nodes
+    pub fn prune_expired(&mut self, now: SystemTime) -> Vec<NodeId> {
+        let expired = self
+            .allowed_nodes
+            .iter()
+            .filter(|(_, grant)| grant.is_expired(now))
+            .map(|(node, _)| *node)
+            .collect::<Vec<_>>();
+        for node in &expired {
+            self.allowed_nodes.remove(node);
         }
+        expired
+    }
+
+    /// Make sure there is still someone who can change the config remotely
+    ///
+    /// Admins with an expiry or limited requests don't count, they can't be
+    /// relied on. The owner always counts.
+    fn check_admins(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.owner.is_some()
+                || self
+                    .allowed_nodes
+                    .values()
+                    .any(|grant| grant.role == Role::Admin && grant.is_permanent()),
+            "refusing to remove the last admin"
+        );
+        Ok(())
     }
 
     pub fn default_path() -> anyhow::Result<PathBuf> {
@@ -84,25 +500,99 @@ impl Config {
         Ok(dir.join("config.toml"))
     }
 
+    /// Directory for user provided sound clips
+    pub fn sounds_dir() -> anyhow::Result<PathBuf> {
+        let dir = munin_data_root()?;
+        Ok(dir.join("sounds"))
+    }
+
+    /// Log of all requests, see [crate::AuditLog]
+    pub fn audit_log_path() -> anyhow::Result<PathBuf> {
+        let dir = munin_data_root()?;
+        Ok(dir.join("audit.jsonl"))
+    }
+
+    /// Pending pairing secrets, see [crate::Pairings]
+    pub fn pairings_path() -> anyhow::Result<PathBuf> {
+        let dir = munin_data_root()?;
+        Ok(dir.join("pairings.json"))
+    }
+
+    /// Unknown nodes that have tried to connect, see [crate::AccessRequests]
+    pub fn access_requests_path() -> anyhow::Result<PathBuf> {
+        let dir = munin_data_root()?;
+        Ok(dir.join("access_requests.json"))
+    }
+
+    /// Load and validate the config from [Config::default_path]
+    ///
+    /// A config from an older version is upgraded and saved, after keeping a
+    /// copy of the old file. If anything goes wrong, the file is left alone.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::default_path()?;
+        tracing::info!("Loading config from {}", path.display());
+        let data = std::fs::read_to_string(&path)?;
+        let (table, migrated) = munin_proto::upgrade_config(&data, MIGRATIONS)?;
+        let config: TomlConfig = toml::Value::Table(table).try_into()?;
+        let config = Self::try_from(config)?;
+        config.validate()?;
+        if let Some(version) = migrated {
+            let backup = munin_proto::backup_config(&path, version)?;
+            tracing::info!(
+                "Upgraded config from version {}, the old one is at {}",
+                version,
+                backup.display()
+            );
+            config.save()?;
+        }
+        Ok(config)
+    }
+
+    /// Check for settings that parse, but would keep the daemon from working
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_name(&self.name)?;
+        let limits = &self.limits;
+        anyhow::ensure!(
+            limits.max_connections > 0
+                && limits.max_connections_per_node > 0
+                && limits.connections_per_minute > 0
+                && limits.connections_per_minute_per_node > 0,
+            "connection limits must not be 0"
+        );
+        anyhow::ensure!(
+            limits.ban_secs <= limits.max_ban_secs,
+            "ban_secs must not be longer than max_ban_secs"
+        );
+        let timeouts = &self.timeouts;
+        anyhow::ensure!(
+            timeouts.handshake_secs > 0
+                && timeouts.read_secs > 0
+                && timeouts.handler_secs > 0
+                && timeouts.close_secs > 0,
+            "timeouts must not be 0"
+        );
+        let mut ids = BTreeSet::new();
+        for alarm in &self.alarms {
+            anyhow::ensure!(ids.insert(alarm.id), "duplicate alarm id {}", alarm.id);
+        }
+        Ok(())
+    }
+
     pub fn get_or_create() -> anyhow::Result<Self> {
         let dir = munin_data_root()?;
         std::fs::create_dir_all(&dir)?;
         let path = dir.join("config.toml");
         if path.exists() {
-            tracing::info!("Loading config from {}", path.display());
-            let data = std::fs::read_to_string(&path)?;
-            let config: TomlConfig = toml::from_str(&data)?;
-            let config = Self::try_from(config)?;
-            Ok(config)
+            Self::load()
         } else {
             tracing::info!("Creating new config at {}", path.display());
             let config = Self {
-                name: "munin-daemon".to_string(),
-                secret_key: iroh_net::key::SecretKey::generate(),
                 allowed_nodes: Config::initial_allowed_nodes()?,
+                owner: Config::initial_owner()?,
+                ..Self::new(iroh_net::key::SecretKey::generate())
             };
             let data = toml::to_string_pretty(&TomlConfig::from(config.clone()))?;
-            std::fs::write(&path, data)?;
+            write_atomic(&path, data.as_bytes())?;
             Ok(config)
         }
     }
@@ -113,7 +603,7 @@ impl Config {
         let path = dir.join("config.toml");
         let data = toml::to_string_pretty(&TomlConfig::from(self.clone()))?;
         tracing::info!("Saving config to {}", path.display());
-        std::fs::write(&path, data)?;
-        Ok(())
+        // a reload never sees half of the file
+        write_atomic(&path, data.as_bytes())
     }
 }
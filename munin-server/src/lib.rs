@@ -1,128 +1,904 @@
-use bytes::Bytes;
+use anyhow::Context;
 use futures::stream::StreamExt;
-use iroh_net::{endpoint, ticket::NodeTicket, NodeId};
-use std::collections::BTreeSet;
+use iroh_net::{
+    endpoint::{self, ConnectionType},
+    ticket::NodeTicket,
+    NodeId,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
 mod config;
 pub use config::Config;
 
+mod audit;
+pub use audit::AuditLog;
+
+mod pairing;
+pub use pairing::Pairings;
+
+mod access;
+pub use access::AccessRequests;
+
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(feature = "audio")]
+use audio::DEFAULT_VOLUME;
+#[cfg(feature = "audio")]
+pub use audio::{
+    unique_device_names, AudioBackend, AudioPlayer, Clip, NullBackend, PlayedClip, RodioBackend,
+};
+
+#[cfg(feature = "audio")]
+mod alarms;
+#[cfg(feature = "audio")]
+pub use alarms::is_due;
+
+#[cfg(feature = "audio")]
+mod sounds;
+#[cfg(feature = "audio")]
+pub use sounds::SoundCatalog;
+
+mod limits;
+pub use limits::{ConnectionLimiter, Limits, Permit, Rejection, Timeouts};
+
+mod network;
+pub use network::{DiscoveryMode, Network, RelayServer, RelayTls};
+
+#[cfg(feature = "relay")]
+mod relay_server;
+
+mod protect;
+pub use protect::{ProtectedProcesses, BUILTIN_PROTECTED};
+
+mod watch;
+
 mod os;
-use os::{
-    get_uptime, kill_process_by_id, list_processes, play_sound_on_default_device, shutdown_system,
+use os::{get_uptime, kill_process_by_id, list_processes, shutdown_system};
+
+use munin_proto::{
+    AuditEntry, AuditLogResponse, AuditOutcome, Capability, Error, ListAccessRequestsResponse,
+    ListAllowedNodesResponse, ListProcessesResponse, PairingSecret, RelayConfig, Request, Response,
+    Role, StatsResponse, VersionResponse,
+};
+#[cfg(feature = "audio")]
+use munin_proto::{
+    DevicePlayback, ListAlarmsResponse, ListAudioDevicesResponse, ListSoundsResponse,
+    PlayAudioRequest, PlaybackStatus,
 };
 
-use munin_proto::{AudioSource, ListProcessesResponse, Request};
+/// State shared by all connections
+#[derive(Debug, Clone)]
+struct State {
+    /// The daemon config, saved whenever a request changes it
+    config: Arc<Mutex<Config>>,
+    audit: Arc<AuditLog>,
+    pairings: Arc<Pairings>,
+    access: Arc<AccessRequests>,
+    limiter: ConnectionLimiter,
+    started: Instant,
+    #[cfg(feature = "audio")]
+    catalog: Arc<SoundCatalog>,
+    #[cfg(feature = "audio")]
+    player: AudioPlayer,
+}
+
+/// How often expired grants are removed, they are also checked on connect
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// How long to wait for a relay before printing a ticket without one
+const RELAY_WAIT: Duration = Duration::from_secs(5);
+/// How long to wait for more changes to the config file before reloading it
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+
+/// The optional features this daemon was built with
+fn capabilities() -> BTreeSet<Capability> {
+    #[allow(unused_mut)]
+    let mut capabilities = BTreeSet::new();
+    #[cfg(feature = "audio")]
+    capabilities.insert(Capability::Audio);
+    capabilities
+}
+
+/// What the process hosting the daemon can ask [run] to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Drain connections and return
+    Shutdown,
+    /// Load the config file again
+    Reload,
+}
 
 pub async fn run(
     config: Config,
-    mut shutdown: tokio::sync::mpsc::UnboundedReceiver<()>,
+    mut control: tokio::sync::mpsc::UnboundedReceiver<Control>,
 ) -> anyhow::Result<()> {
     println!("I am {}", config.secret_key.public());
-    let endpoint = iroh_net::Endpoint::builder()
-        .discovery(Box::new(
-            iroh_net::discovery::pkarr::PkarrPublisher::n0_dns(config.secret_key.clone()),
-        ))
+    let state = State {
+        config: Arc::new(Mutex::new(config.clone())),
+        audit: Arc::new(AuditLog::new(Config::audit_log_path()?)?),
+        pairings: Arc::new(Pairings::new(Config::pairings_path()?)),
+        access: Arc::new(AccessRequests::new(Config::access_requests_path()?)),
+        limiter: ConnectionLimiter::new(config.limits.clone()),
+        started: Instant::now(),
+        #[cfg(feature = "audio")]
+        catalog: {
+            let sounds_dir = Config::sounds_dir()?;
+            std::fs::create_dir_all(&sounds_dir)?;
+            Arc::new(SoundCatalog::load(&sounds_dir)?)
+        },
+        #[cfg(feature = "audio")]
+        player: AudioPlayer::spawn()?,
+    };
+    let network = &config.network;
+    let mut builder = iroh_net::Endpoint::builder()
         .secret_key(config.secret_key.clone())
         .alpns(vec![munin_proto::ALPN.into()])
-        .bind()
-        .await?;
-    endpoint.watch_home_relay().next().await;
-    let info = endpoint.node_addr().await?;
-    tracing::info!("Listening on {:?}", info);
-    let ticket = NodeTicket::from(info);
-    println!("My ticket: {}", ticket);
+        .relay_mode(network.relay.mode());
+    let name = {
+        let config = state.config.clone();
+        move || config.lock().unwrap().name.clone()
+    };
+    if let Some(discovery) = network.discovery(&config.secret_key, name)? {
+        builder = builder.discovery(discovery);
+    }
+    match network.bind_addr {
+        Some(SocketAddr::V4(addr)) => builder = builder.bind_addr_v4(addr),
+        Some(SocketAddr::V6(addr)) => builder = builder.bind_addr_v6(addr),
+        None => {}
+    }
+    let endpoint = builder.bind().await?;
+    #[cfg(feature = "relay")]
+    let relay_server = match &network.relay_server {
+        Some(relay_server) => Some(relay_server::spawn(relay_server).await?),
+        None => None,
+    };
+    #[cfg(not(feature = "relay"))]
+    anyhow::ensure!(
+        network.relay_server.is_none(),
+        "the daemon was built without the relay feature, remove [network.relay_server]"
+    );
+    #[cfg(feature = "audio")]
+    let alarms = tokio::spawn(alarms::run(
+        state.config.clone(),
+        state.catalog.clone(),
+        state.player.clone(),
+    ));
+    let prune = tokio::spawn({
+        let state = state.clone();
+        async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                prune_expired_grants(&state);
+            }
+        }
+    });
+    let reload = match watch::watch_config(&Config::default_path()?) {
+        Ok((watcher, mut changed)) => {
+            let state = state.clone();
+            Some(tokio::spawn(async move {
+                let _watcher = watcher;
+                while changed.recv().await.is_some() {
+                    // editors often write a file in several steps
+                    tokio::time::sleep(RELOAD_DELAY).await;
+                    while changed.try_recv().is_ok() {}
+                    reload_config(&state);
+                }
+            }))
+        }
+        Err(e) => {
+            tracing::warn!("Not watching the config, reload with SIGHUP: {:#}", e);
+            None
+        }
+    };
+    // don't wait for the relay before accepting, it may not be reachable at all
+    let announce = tokio::spawn({
+        let endpoint = endpoint.clone();
+        let relay = network.relay != RelayConfig::Disabled;
+        async move {
+            if relay
+                && tokio::time::timeout(RELAY_WAIT, endpoint.watch_home_relay().next())
+                    .await
+                    .is_err()
+            {
+                tracing::warn!("No relay reachable, the ticket only has direct addresses");
+            }
+            match endpoint.node_addr().await {
+                Ok(info) => {
+                    tracing::info!("Listening on {:?}", info);
+                    println!("My ticket: {}", NodeTicket::from(info));
+                }
+                Err(e) => tracing::warn!("Failed to get our addresses: {}", e),
+            }
+        }
+    });
+    let mut connections = JoinSet::new();
+    let cancel = CancellationToken::new();
     loop {
         tokio::select! {
-            _ = shutdown.recv() => {
-                break;
-            }
+            control = control.recv() => match control {
+                Some(Control::Reload) => reload_config(&state),
+                Some(Control::Shutdown) | None => break,
+            },
             Some(incoming) = endpoint.accept() => {
-                tokio::spawn(handle_incoming(incoming, config.allowed_nodes.clone()));
+                let addr = incoming.remote_address();
+                match state.limiter.admit(addr, Instant::now()) {
+                    Ok(permit) => {
+                        let cancel = cancel.clone();
+                        let handler =
+                            handle_incoming(incoming, permit, endpoint.clone(), state.clone());
+                        connections.spawn(async move {
+                            tokio::select! {
+                                res = handler => res,
+                                _ = cancel.cancelled() => Err(anyhow::anyhow!("aborted by shutdown")),
+                            }
+                        });
+                    }
+                    Err(rejection) => {
+                        tracing::debug!("Refusing connection from {}: {:?}", addr, rejection);
+                        incoming.refuse();
+                    }
+                }
+            }
+            Some(res) = connections.join_next() => {
+                log_connection_result(res);
             }
         }
     }
+    let drain = state.config.lock().unwrap().timeouts.drain();
+    tracing::info!(
+        "Shutting down, waiting up to {:?} for {} connections",
+        drain,
+        connections.len()
+    );
+    let drained = tokio::time::timeout(drain, async {
+        while let Some(res) = connections.join_next().await {
+            log_connection_result(res);
+        }
+    })
+    .await;
+    let aborted = connections.len();
+    if drained.is_err() {
+        cancel.cancel();
+        while let Some(res) = connections.join_next().await {
+            log_connection_result(res);
+        }
+        tracing::warn!("Aborted {} connections", aborted);
+    }
+    endpoint.close(0u32.into(), b"daemon shutting down").await?;
+    #[cfg(feature = "relay")]
+    if let Some(relay_server) = relay_server {
+        if let Err(e) = relay_server.shutdown().await {
+            tracing::warn!("Relay server failed: {:#}", e);
+        }
+    }
+    tracing::info!("Shut down, {} connections aborted", aborted);
+    audit_daemon_event(
+        &state,
+        format!("DaemonShutdown {{ aborted: {} }}", aborted),
+        AuditOutcome::Ok,
+    );
+    // the process may exit right after this returns
+    state.audit.flush().await;
+    #[cfg(feature = "audio")]
+    alarms.abort();
+    prune.abort();
+    announce.abort();
+    if let Some(reload) = reload {
+        reload.abort();
+    }
     Ok(())
 }
 
-const WAKE_UP: &[u8] = include_bytes!("../assets/wake_up.mp3");
-const ALARM: &[u8] = include_bytes!("../assets/alarm.mp3");
-const RICKROLL: &[u8] = include_bytes!("../assets/rickroll.mp3");
+fn log_connection_result(res: Result<anyhow::Result<()>, tokio::task::JoinError>) {
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::debug!("Connection failed: {:#}", e),
+        Err(e) => tracing::warn!("Connection task failed: {}", e),
+    }
+}
+
+/// Serialize the response, and return it with its outcome for the audit log
+fn encode_response<T: Serialize>(
+    response: &Response<T>,
+) -> anyhow::Result<(Vec<u8>, AuditOutcome)> {
+    let data = postcard::to_allocvec(response)?;
+    let outcome = match response {
+        Ok(_) => AuditOutcome::Ok,
+        Err(e) => AuditOutcome::Error(e.clone()),
+    };
+    Ok((data, outcome))
+}
+
+/// Send the response and return its outcome for the audit log
+async fn send_response<T: Serialize>(
+    send: &mut endpoint::SendStream,
+    response: &Response<T>,
+) -> anyhow::Result<AuditOutcome> {
+    let (data, outcome) = encode_response(response)?;
+    send.write_all(&data).await?;
+    send.finish()?;
+    Ok(outcome)
+}
+
+/// Wait for the client to close the connection after the response, but not forever
+async fn wait_closed(connection: &endpoint::Connection, timeouts: &Timeouts) {
+    if tokio::time::timeout(timeouts.close(), connection.closed())
+        .await
+        .is_err()
+    {
+        connection.close(0u32.into(), b"timeout");
+    }
+}
 
 async fn handle_incoming(
     incoming: endpoint::Incoming,
-    allowed_nodes: BTreeSet<NodeId>,
+    mut permit: Permit,
+    endpoint: iroh_net::Endpoint,
+    state: State,
 ) -> anyhow::Result<()> {
+    let timeouts = state.config.lock().unwrap().timeouts.clone();
+    let addr = incoming.remote_address();
     let accepting = incoming.accept()?;
-    let connection = accepting.await?;
+    let connection = tokio::time::timeout(timeouts.handshake(), accepting)
+        .await
+        .context("handshake timed out")??;
     let remote_node_id = iroh_net::endpoint::get_remote_node_id(&connection)?;
-    if !allowed_nodes.contains(&remote_node_id) {
-        connection.close(403u32.into(), b"unauthorized node");
+    let direct_ip = direct_ip(&endpoint, remote_node_id);
+    // the address iroh shows for the node, and its real one if it is connected directly
+    let ips = [Some(addr.ip()), direct_ip]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if let Err(rejection) =
+        state
+            .limiter
+            .admit_node(&mut permit, remote_node_id, direct_ip, Instant::now())
+    {
+        tracing::debug!(
+            "Closing connection from {}: {:?}",
+            remote_node_id,
+            rejection
+        );
+        let reason = match rejection {
+            Rejection::Banned => &b"banned"[..],
+            _ => b"too many connections",
+        };
+        connection.close(1u32.into(), reason);
+        return Ok(());
+    }
+    let audit = |request: String, outcome: AuditOutcome| {
+        state.audit.record(&AuditEntry {
+            time: SystemTime::now(),
+            node: remote_node_id,
+            request,
+            outcome,
+        })
+    };
+    let Ok(stream) = tokio::time::timeout(timeouts.read(), connection.accept_bi()).await else {
+        tracing::debug!("No request from {}", remote_node_id);
+        connection.close(0u32.into(), b"timeout");
+        return Ok(());
+    };
+    let (mut send, mut recv) = stream?;
+    let read = recv.read_to_end(munin_proto::MAX_REQUEST_SIZE);
+    let Ok(msg) = tokio::time::timeout(timeouts.read(), read).await else {
+        tracing::debug!("Timed out reading the request of {}", remote_node_id);
+        // the error has the same encoding for all responses
+        send_response(&mut send, &Err::<(), _>(Error::Timeout)).await?;
+        wait_closed(&connection, &timeouts).await;
+        return Ok(());
+    };
+    let msg = postcard::from_bytes::<munin_proto::Request>(&msg?)?;
+    let (msg, delegation) = match msg {
+        Request::Delegated { chain, request } => (*request, Some(chain)),
+        msg => (msg, None),
+    };
+    let request = match &msg {
+        // keep the secret out of the log
+        Request::Pair(_) => "Pair".to_string(),
+        msg if delegation.is_some() => format!("{:?} (delegated)", msg),
+        msg => format!("{:?}", msg),
+    };
+    if let Request::Pair(secret) = msg {
+        tracing::info!("Pairing {}", remote_node_id);
+        let response = pair(&state, remote_node_id, &secret).map_err(Error::failed);
+        let outcome = send_response(&mut send, &response).await?;
+        audit(request, outcome);
+        wait_closed(&connection, &timeouts).await;
+        return Ok(());
+    }
+    let now = SystemTime::now();
+    let grant = state
+        .config
+        .lock()
+        .unwrap()
+        .grant_for(remote_node_id, delegation.as_ref(), now);
+    let grant = match grant {
+        Ok(Some(grant)) if grant.is_expired(now) => {
+            prune_expired_grants(&state);
+            None
+        }
+        Ok(grant) => grant,
+        Err(e) => {
+            tracing::info!("Invalid delegation from {}: {}", remote_node_id, e);
+            state
+                .limiter
+                .unauthorized(remote_node_id, &ips, Instant::now());
+            let response = Err::<(), _>(Error::InvalidDelegation(e.to_string()));
+            send_response(&mut send, &response).await?;
+            audit(request, AuditOutcome::Unauthorized);
+            wait_closed(&connection, &timeouts).await;
+            return Ok(());
+        }
+    };
+    let Some(grant) = grant else {
+        if let Request::GetVersion = msg {
+            // answered without side effects, so controllers can look at daemons they don't know
+            let error = access_status(&state, remote_node_id);
+            send_response(&mut send, &Err::<(), _>(error)).await?;
+            audit(request, AuditOutcome::Unauthorized);
+            wait_closed(&connection, &timeouts).await;
+            return Ok(());
+        }
         tracing::info!(
             "Unauthorized node attempted to connect: {:?}",
             remote_node_id
         );
+        state
+            .limiter
+            .unauthorized(remote_node_id, &ips, Instant::now());
+        let name = match msg {
+            Request::RequestAccess { name } => name,
+            _ => None,
+        };
+        let queued = state.access.contains(remote_node_id).unwrap_or(true);
+        let error = if !queued && !state.limiter.new_access_request(direct_ip, Instant::now()) {
+            tracing::info!(
+                "Not queueing {}, too many access requests from {:?}",
+                remote_node_id,
+                direct_ip
+            );
+            Error::PendingApproval
+        } else {
+            match state.access.record(remote_node_id, name) {
+                Ok(access) if access.denied => Error::AccessDenied,
+                Ok(_) => Error::PendingApproval,
+                Err(e) => {
+                    tracing::warn!("Failed to record access request: {}", e);
+                    Error::PendingApproval
+                }
+            }
+        };
+        // the error has the same encoding for all responses
+        send_response(&mut send, &Err::<(), _>(error)).await?;
+        audit(request, AuditOutcome::Unauthorized);
+        wait_closed(&connection, &timeouts).await;
+        return Ok(());
+    };
+    state.limiter.authorized(remote_node_id);
+    let role = grant.role;
+    let required = msg.required_role();
+    if role < required {
+        tracing::info!("Denied {:?} to {} with role {}", msg, remote_node_id, role);
+        // the error has the same encoding for all responses
+        let response = Err::<(), _>(Error::PermissionDenied { role, required });
+        let outcome = send_response(&mut send, &response).await?;
+        audit(request, outcome);
+        wait_closed(&connection, &timeouts).await;
         return Ok(());
     }
-    let (mut send, mut recv) = connection.accept_bi().await?;
-    let msg = recv.read_to_end(munin_proto::MAX_REQUEST_SIZE).await?;
-    let msg = postcard::from_bytes::<munin_proto::Request>(&msg)?;
-    match msg {
-        Request::ListProcesses => {
-            tracing::info!("Listing processes");
-            let tasks = list_processes();
-            let response = ListProcessesResponse { tasks };
-            let response = postcard::to_allocvec(&response)?;
-            send.write_all(&response).await?;
-            send.finish()?;
-            connection.closed().await;
-        }
-        Request::KillProcess(pid) => {
-            tracing::info!("Killing process {}", pid);
-            let res = kill_process_by_id(pid);
-            let response = res
-                .err()
-                .map(|e| e.to_string())
-                .unwrap_or_else(|| "OK".to_string());
-            let response = postcard::to_allocvec(&response)?;
-            send.write_all(&response).await?;
-            send.finish()?;
-            connection.closed().await;
-        }
-        Request::GetSystemInfo => {
-            tracing::info!("Getting system info");
-            let uptime = get_uptime()?;
-            let hostname = hostname::get()?
-                .into_string()
-                .map_err(|_| anyhow::anyhow!("Invalid hostname"))?;
-            let response = munin_proto::SysInfoResponse { uptime, hostname };
-            let response = postcard::to_allocvec(&response)?;
-            send.write_all(&response).await?;
-            send.finish()?;
-            connection.closed().await;
-        }
-        Request::PlayAudio(source) => {
-            let audio_data: Bytes = match source {
-                AudioSource::WakeUp => WAKE_UP.into(),
-                AudioSource::Alarm => ALARM.into(),
-                AudioSource::RickRoll => RICKROLL.into(),
-                AudioSource::Url(url) => {
-                    anyhow::bail!("URL playback not implemented: {}", url);
-                }
-            };
-            let response = play_sound_on_default_device(audio_data);
-            let response = match response {
-                Ok(_) => "OK".to_string(),
-                Err(e) => e.to_string(),
-            };
-            let response = postcard::to_allocvec(&response)?;
-            send.write_all(&response).await?;
-            send.finish()?;
-            connection.closed().await;
-        }
-        Request::Shutdown => {
-            shutdown_system();
-        }
-    }
-    connection.closed().await;
+    if !grant.permits(msg.kind()) {
+        tracing::info!("Denied {:?} to {}, not granted", msg, remote_node_id);
+        let response = Err::<(), _>(Error::NotGranted(msg.kind()));
+        let outcome = send_response(&mut send, &response).await?;
+        audit(request, outcome);
+        wait_closed(&connection, &timeouts).await;
+        return Ok(());
+    }
+    let shutdown = matches!(msg, Request::Shutdown);
+    let handler = async {
+        let response = match msg {
+            Request::GetVersion => {
+                tracing::info!("Getting version");
+                let response = Ok::<_, Error>(VersionResponse {
+                    protocol_version: munin_proto::PROTOCOL_VERSION,
+                    name: state.config.lock().unwrap().name.clone(),
+                    capabilities: capabilities(),
+                });
+                encode_response(&response)?
+            }
+            Request::ListProcesses => {
+                tracing::info!("Listing processes");
+                // scanning processes can be slow, don't block the runtime and the timeout
+                let tasks = tokio::task::spawn_blocking(list_processes).await?;
+                let response = Ok::<_, Error>(ListProcessesResponse { tasks });
+                encode_response(&response)?
+            }
+            Request::KillProcess(pid) => {
+                tracing::info!("Killing process {}", pid);
+                let response = kill_process(&state, pid);
+                encode_response(&response)?
+            }
+            Request::GetSystemInfo => {
+                tracing::info!("Getting system info");
+                let name = state.config.lock().unwrap().name.clone();
+                let response = tokio::task::spawn_blocking(move || system_info(name))
+                    .await?
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::PlayAudio(request) => {
+                tracing::info!("Playing audio {}", request.source);
+                let response = play_audio(&state.catalog, &state.player, request)
+                    .await
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::StopAudio => {
+                tracing::info!("Stopping audio");
+                let response = state.player.stop().map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::ListAudioDevices => {
+                tracing::info!("Listing audio devices");
+                let response = state
+                    .player
+                    .devices()
+                    .await
+                    .map(|devices| ListAudioDevicesResponse { devices })
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::ListSounds => {
+                tracing::info!("Listing sounds");
+                let response = Ok::<_, Error>(ListSoundsResponse {
+                    sounds: state.catalog.list(),
+                });
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::AddAlarm(spec) => {
+                tracing::info!("Adding alarm {}", spec);
+                let response = add_alarm(&state, spec).map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::ListAlarms => {
+                tracing::info!("Listing alarms");
+                let alarms = state.config.lock().unwrap().alarms.clone();
+                let response = Ok::<_, Error>(ListAlarmsResponse { alarms });
+                encode_response(&response)?
+            }
+            #[cfg(feature = "audio")]
+            Request::RemoveAlarm(id) => {
+                tracing::info!("Removing alarm {}", id);
+                let response = remove_alarm(&state, id).map_err(Error::failed);
+                encode_response(&response)?
+            }
+            #[cfg(not(feature = "audio"))]
+            Request::PlayAudio(_)
+            | Request::StopAudio
+            | Request::ListAudioDevices
+            | Request::ListSounds
+            | Request::AddAlarm(_)
+            | Request::ListAlarms
+            | Request::RemoveAlarm(_) => {
+                tracing::info!("Rejecting audio request, audio is not supported");
+                let response = Err::<(), _>(Error::Unsupported(Capability::Audio));
+                encode_response(&response)?
+            }
+            Request::ListAllowedNodes => {
+                tracing::info!("Listing allowed nodes");
+                let nodes = state.config.lock().unwrap().allowed_nodes.clone();
+                let response = Ok::<_, Error>(ListAllowedNodesResponse { nodes });
+                encode_response(&response)?
+            }
+            Request::AllowNode { node, grant } => {
+                tracing::info!("Allowing {} as {}", node, grant.role);
+                let response = update_config(&state, |config| config.allow_node(node, grant))
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::DisallowNode(node) => {
+                tracing::info!("Disallowing {}", node);
+                let response = update_config(&state, |config| config.disallow_node(node))
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::GetAuditLog(query) => {
+                tracing::info!("Getting audit log");
+                let response = state
+                    .audit
+                    .query(&query)
+                    .await
+                    .map(|entries| AuditLogResponse { entries })
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::Pair(_) => unreachable!("handled before the role check"),
+            Request::RequestAccess { .. } => {
+                tracing::info!("Access requested by allowed node");
+                encode_response(&Ok::<_, Error>(role))?
+            }
+            Request::ListAccessRequests => {
+                tracing::info!("Listing access requests");
+                let response = state
+                    .access
+                    .list()
+                    .map(|requests| ListAccessRequestsResponse { requests })
+                    .map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::ApproveAccess { node, role } => {
+                tracing::info!("Approving access for {} as {}", node, role);
+                let response = approve_access(&state, node, role).map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::DenyAccess(node) => {
+                tracing::info!("Denying access for {}", node);
+                let response = state.access.deny(node).map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::GetStats => {
+                tracing::info!("Getting stats");
+                let response = Ok::<_, Error>(StatsResponse {
+                    uptime: state.started.elapsed(),
+                    connections: state.limiter.stats(Instant::now()),
+                });
+                encode_response(&response)?
+            }
+            Request::SetName(name) => {
+                tracing::info!("Renaming the daemon to {}", name);
+                let response =
+                    update_config(&state, |config| config.set_name(name)).map_err(Error::failed);
+                encode_response(&response)?
+            }
+            Request::Delegated { .. } => {
+                let response = Err::<(), _>(Error::failed("nested delegations are not supported"));
+                encode_response(&response)?
+            }
+            Request::Shutdown => {
+                tracing::info!("Shutting down");
+                encode_response(&Ok::<_, Error>(()))?
+            }
+        };
+        anyhow::Ok(response)
+    };
+    let (data, outcome) = match tokio::time::timeout(timeouts.handler(), handler).await {
+        Ok(response) => response?,
+        Err(_) => {
+            tracing::info!("Timed out handling {}", request);
+            encode_response(&Err::<(), _>(Error::Timeout))?
+        }
+    };
+    send.write_all(&data).await?;
+    send.finish()?;
+    audit(request, outcome);
+    if shutdown {
+        shutdown_system();
+    }
+    wait_closed(&connection, &timeouts).await;
     Ok(())
 }
+
+fn system_info(name: String) -> anyhow::Result<munin_proto::SysInfoResponse> {
+    let uptime = get_uptime()?;
+    let hostname = hostname::get()?
+        .into_string()
+        .map_err(|_| anyhow::anyhow!("Invalid hostname"))?;
+    Ok(munin_proto::SysInfoResponse {
+        name,
+        hostname,
+        uptime,
+    })
+}
+
+/// The IP address of a node that is connected directly, not through a relay
+fn direct_ip(endpoint: &iroh_net::Endpoint, node: NodeId) -> Option<IpAddr> {
+    match endpoint.remote_info(node)?.conn_type {
+        ConnectionType::Direct(addr) | ConnectionType::Mixed(addr, _) => Some(addr.ip()),
+        ConnectionType::Relay(_) | ConnectionType::None => None,
+    }
+}
+
+/// Why a node without a grant is not allowed, without recording anything
+fn access_status(state: &State, node: NodeId) -> Error {
+    let requests = match state.access.list() {
+        Ok(requests) => requests,
+        Err(e) => {
+            tracing::warn!("Failed to load access requests: {}", e);
+            return Error::NotAllowed;
+        }
+    };
+    match requests.iter().find(|r| r.node == node) {
+        Some(request) if request.denied => Error::AccessDenied,
+        Some(_) => Error::PendingApproval,
+        None => Error::NotAllowed,
+    }
+}
+
+/// Kill a process, unless it is protected
+fn kill_process(state: &State, pid: u32) -> Result<(), Error> {
+    let protected = ProtectedProcesses::new(&state.config.lock().unwrap().protected_processes);
+    protected.check(pid)?;
+    kill_process_by_id(pid).map_err(Error::failed)
+}
+
+/// Change the config and save it
+///
+/// The change is only applied if `f` succeeds and the config could be saved.
+fn update_config<T>(
+    state: &State,
+    f: impl FnOnce(&mut Config) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut config = state.config.lock().unwrap();
+    let mut updated = config.clone();
+    let res = f(&mut updated)?;
+    updated.save()?;
+    *config = updated;
+    Ok(res)
+}
+
+/// Record something the daemon did on its own in the audit log, under its own node id
+fn audit_daemon_event(state: &State, request: String, outcome: AuditOutcome) {
+    let node = state.config.lock().unwrap().secret_key.public();
+    state.audit.record(&AuditEntry {
+        time: SystemTime::now(),
+        node,
+        request,
+        outcome,
+    });
+}
+
+/// Replace the config with the one from the config file, keeping the old one if that fails
+///
+/// The new config applies to new connections, connections in flight finish
+/// with the config they started with. Does nothing if the file has not
+/// changed, e.g. because the daemon saved it itself.
+fn reload_config(state: &State) {
+    let result = (|| {
+        // hold the lock while reading, so a concurrent save is not undone
+        let mut current = state.config.lock().unwrap();
+        let config = Config::load()?;
+        if config == *current {
+            return Ok(false);
+        }
+        anyhow::ensure!(
+            config.secret_key.public() == current.secret_key.public(),
+            "the secret key can't be changed while the daemon is running"
+        );
+        if config.network != current.network {
+            tracing::warn!("Network settings only apply after a restart");
+        }
+        state.limiter.set_limits(config.limits.clone());
+        *current = config;
+        Ok(true)
+    })();
+    let outcome = match result {
+        Ok(false) => {
+            tracing::debug!("Config unchanged");
+            return;
+        }
+        Ok(true) => {
+            tracing::info!("Reloaded config");
+            AuditOutcome::Ok
+        }
+        Err(e) => {
+            tracing::warn!("Keeping the old config, failed to reload: {:#}", e);
+            AuditOutcome::Error(Error::failed(format!("{:#}", e)))
+        }
+    };
+    audit_daemon_event(state, "ReloadConfig".to_string(), outcome);
+}
+
+/// Remove expired grants from the config and record them in the audit log
+fn prune_expired_grants(state: &State) {
+    let now = SystemTime::now();
+    let any_expired = state
+        .config
+        .lock()
+        .unwrap()
+        .allowed_nodes
+        .values()
+        .any(|grant| grant.is_expired(now));
+    if !any_expired {
+        // don't rewrite the config every time
+        return;
+    }
+    match update_config(state, |config| Ok(config.prune_expired(now))) {
+        Ok(expired) => {
+            for node in expired {
+                tracing::info!("Grant of {} has expired", node);
+                state.audit.record(&AuditEntry {
+                    time: now,
+                    node,
+                    request: "GrantExpired".to_string(),
+                    outcome: AuditOutcome::Ok,
+                });
+            }
+        }
+        Err(e) => tracing::warn!("Failed to prune expired grants: {}", e),
+    }
+}
+
+/// Redeem a pairing secret and allow the node, never lowering an existing role
+fn pair(state: &State, node: NodeId, secret: &PairingSecret) -> anyhow::Result<Role> {
+    state
+        .pairings
+        .redeem_with(secret, |granted| {
+            update_config(state, |config| {
+                let role = match config.allowed_nodes.get(&node) {
+                    Some(existing) => granted.max(existing.role),
+                    None => granted,
+                };
+                config.allow_node(node, role)?;
+                Ok(role)
+            })
+        })
+        .inspect(|_| {
+            // the node may have asked for access before it got the ticket
+            state.access.remove(node).ok();
+        })
+}
+
+/// Allow a node from the access request queue
+fn approve_access(state: &State, node: NodeId, role: Role) -> anyhow::Result<()> {
+    // fail before changing anything if there is no such request
+    anyhow::ensure!(
+        state
+            .access
+            .list()?
+            .iter()
+            .any(|request| request.node == node),
+        "no access request from {}",
+        node
+    );
+    update_config(state, |config| config.allow_node(node, role)).inspect(|_| {
+        state.access.remove(node).ok();
+    })
+}
+
+/// Validate and store a new alarm, returning its id
+#[cfg(feature = "audio")]
+fn add_alarm(state: &State, spec: munin_proto::AlarmSpec) -> anyhow::Result<u32> {
+    alarms::validate(&state.catalog, &spec)?;
+    update_config(state, |config| config.add_alarm(spec))
+}
+
+#[cfg(feature = "audio")]
+fn remove_alarm(state: &State, id: u32) -> anyhow::Result<()> {
+    update_config(state, |config| config.remove_alarm(id))
+}
+
+/// Resolve and play a sound, reporting the outcome per device
+#[cfg(feature = "audio")]
+pub async fn play_audio(
+    catalog: &SoundCatalog,
+    player: &AudioPlayer,
+    request: PlayAudioRequest,
+) -> anyhow::Result<Vec<DevicePlayback>> {
+    let data = catalog.resolve(&request.source)?;
+    let volume = request.volume.unwrap_or(DEFAULT_VOLUME);
+    let queued = player.play(data, volume, request.output).await?;
+    let results = queued.into_iter().map(|(device, playback)| async move {
+        let result = match playback {
+            Ok(_) if !request.wait => Ok(PlaybackStatus::Queued),
+            Ok(playback) => Ok(playback.finished().await),
+            Err(e) => Err(e.to_string()),
+        };
+        DevicePlayback { device, result }
+    });
+    Ok(futures::future::join_all(results).await)
+}
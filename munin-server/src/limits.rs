@@ -0,0 +1,339 @@
+//! Limits on incoming connections, temporary bans for unknown nodes that keep
+//! trying, and deadlines for handling a connection.
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use iroh_net::NodeId;
+use munin_proto::{BannedNode, ConnectionStats};
+use serde::{Deserialize, Serialize};
+
+/// Window for the per minute limits
+const MINUTE: Duration = Duration::from_secs(60);
+/// Window for [Limits::access_requests_per_hour]
+const HOUR: Duration = Duration::from_secs(60 * 60);
+/// Idle nodes are forgotten once more than this many are tracked
+const MAX_TRACKED_NODES: usize = 10_000;
+
+/// Connection limits of the daemon, the `[limits]` section of the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Connections handled at the same time
+    pub max_connections: u32,
+    pub max_connections_per_node: u32,
+    pub connections_per_minute: u32,
+    pub connections_per_minute_per_node: u32,
+    /// Attempts by an unknown node within a minute before it is banned
+    pub unauthorized_per_minute: u32,
+    /// Length of the first ban, doubled for every further one
+    pub ban_secs: u64,
+    pub max_ban_secs: u64,
+    /// New access requests from one IP address within an hour, nodes
+    /// connected through a relay share one allowance
+    pub access_requests_per_hour: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections: 64,
+            max_connections_per_node: 4,
+            connections_per_minute: 600,
+            connections_per_minute_per_node: 60,
+            unauthorized_per_minute: 5,
+            ban_secs: 60,
+            max_ban_secs: 24 * 60 * 60,
+            access_requests_per_hour: 10,
+        }
+    }
+}
+
+/// Deadlines for handling a connection, the `[timeouts]` section of the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// For the QUIC handshake
+    pub handshake_secs: u64,
+    /// For opening the stream and reading the request
+    pub read_secs: u64,
+    /// For handling the request, including waiting for audio to finish playing
+    pub handler_secs: u64,
+    /// For the client to close the connection after the response
+    pub close_secs: u64,
+    /// For connections in flight to finish when the daemon shuts down
+    pub drain_secs: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake_secs: 10,
+            read_secs: 10,
+            handler_secs: 120,
+            close_secs: 10,
+            drain_secs: 10,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake_secs)
+    }
+
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read_secs)
+    }
+
+    pub fn handler(&self) -> Duration {
+        Duration::from_secs(self.handler_secs)
+    }
+
+    pub fn close(&self) -> Duration {
+        Duration::from_secs(self.close_secs)
+    }
+
+    pub fn drain(&self) -> Duration {
+        Duration::from_secs(self.drain_secs)
+    }
+}
+
+/// Why a connection was not admitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Too many connections in total
+    Busy,
+    /// Too many connections from this node
+    NodeLimit,
+    /// The node or its address is banned
+    Banned,
+}
+
+#[derive(Debug, Default)]
+struct NodeState {
+    active: u32,
+    recent: VecDeque<Instant>,
+    unauthorized: VecDeque<Instant>,
+    bans: u32,
+    banned_until: Option<Instant>,
+}
+
+impl NodeState {
+    fn is_idle(&self, now: Instant) -> bool {
+        self.active == 0
+            && self.recent.back().is_none_or(|t| now - *t > MINUTE)
+            && self.banned_until.is_none_or(|until| until <= now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    limits: Limits,
+    active: u32,
+    recent: VecDeque<Instant>,
+    nodes: HashMap<NodeId, NodeState>,
+    /// IP addresses of banned nodes, without the port, which is cheap to change
+    ///
+    /// Before the handshake iroh only shows an address of its own for each
+    /// node. The IP of a direct connection is checked after the handshake.
+    banned_addrs: HashMap<IpAddr, Instant>,
+    /// New access requests by IP address, `None` for relayed connections
+    access_requests: HashMap<Option<IpAddr>, VecDeque<Instant>>,
+    stats: ConnectionStats,
+}
+
+/// Keep only the times within the last minute
+fn prune(times: &mut VecDeque<Instant>, now: Instant) {
+    while times.front().is_some_and(|t| now - *t >= MINUTE) {
+        times.pop_front();
+    }
+}
+
+/// Tracks connections to enforce [Limits], shared by all connections
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                limits,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Replace the limits, e.g. after the config was reloaded
+    ///
+    /// Connections and bans that already exist are kept.
+    pub fn set_limits(&self, limits: Limits) {
+        self.inner.lock().unwrap().limits = limits;
+    }
+
+    /// Check a connection before the handshake, when only its address is known
+    pub fn admit(&self, addr: SocketAddr, now: Instant) -> Result<Permit, Rejection> {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        inner.banned_addrs.retain(|_, until| *until > now);
+        if inner.banned_addrs.contains_key(&addr.ip()) {
+            inner.stats.refused_banned += 1;
+            return Err(Rejection::Banned);
+        }
+        prune(&mut inner.recent, now);
+        if inner.active >= inner.limits.max_connections
+            || inner.recent.len() >= inner.limits.connections_per_minute as usize
+        {
+            inner.stats.refused += 1;
+            return Err(Rejection::Busy);
+        }
+        inner.active += 1;
+        inner.recent.push_back(now);
+        inner.stats.accepted += 1;
+        Ok(Permit {
+            inner: self.inner.clone(),
+            node: None,
+        })
+    }
+
+    /// Check a connection after the handshake, once the node id is known
+    ///
+    /// `ip` is the address of a direct connection, `None` if the node is
+    /// connected through a relay, whose address is shared by many nodes.
+    pub fn admit_node(
+        &self,
+        permit: &mut Permit,
+        node: NodeId,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Rejection> {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        if inner.nodes.len() > MAX_TRACKED_NODES {
+            inner.nodes.retain(|_, state| !state.is_idle(now));
+        }
+        let ip_banned = ip
+            .and_then(|ip| inner.banned_addrs.get(&ip))
+            .is_some_and(|until| *until > now);
+        let state = inner.nodes.entry(node).or_default();
+        if ip_banned || state.banned_until.is_some_and(|until| until > now) {
+            inner.stats.refused_banned += 1;
+            return Err(Rejection::Banned);
+        }
+        prune(&mut state.recent, now);
+        if state.active >= inner.limits.max_connections_per_node
+            || state.recent.len() >= inner.limits.connections_per_minute_per_node as usize
+        {
+            inner.stats.refused_node += 1;
+            return Err(Rejection::NodeLimit);
+        }
+        state.active += 1;
+        state.recent.push_back(now);
+        permit.node = Some(node);
+        Ok(())
+    }
+
+    /// Record an attempt by a node that is not allowed, banning it and its
+    /// IP addresses if it keeps trying
+    ///
+    /// Every further ban is twice as long as the one before, up to the maximum.
+    pub fn unauthorized(&self, node: NodeId, addrs: &[IpAddr], now: Instant) {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        let state = inner.nodes.entry(node).or_default();
+        prune(&mut state.unauthorized, now);
+        state.unauthorized.push_back(now);
+        if state.unauthorized.len() < inner.limits.unauthorized_per_minute as usize {
+            return;
+        }
+        state.unauthorized.clear();
+        let ban = Duration::from_secs(inner.limits.ban_secs)
+            .saturating_mul(2u32.saturating_pow(state.bans))
+            .min(Duration::from_secs(inner.limits.max_ban_secs));
+        state.bans += 1;
+        let until = now + ban;
+        state.banned_until = Some(until);
+        tracing::info!("Banning {} for {:?}", node, ban);
+        for addr in addrs {
+            inner.banned_addrs.insert(*addr, until);
+        }
+        inner.stats.bans += 1;
+    }
+
+    /// Check if a new access request from `ip` may be queued, and count it
+    ///
+    /// `None` is for nodes connected through a relay. Without this, a node
+    /// could push the requests of others out of the queue with new keys.
+    pub fn new_access_request(&self, ip: Option<IpAddr>, now: Instant) -> bool {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        if inner.access_requests.len() > MAX_TRACKED_NODES {
+            inner
+                .access_requests
+                .retain(|_, times| times.back().is_some_and(|t| now - *t < HOUR));
+        }
+        let times = inner.access_requests.entry(ip).or_default();
+        while times.front().is_some_and(|t| now - *t >= HOUR) {
+            times.pop_front();
+        }
+        if times.len() >= inner.limits.access_requests_per_hour as usize {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Forget the bans of a node once it is allowed
+    pub fn authorized(&self, node: NodeId) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(state) = inner.nodes.get_mut(&node) {
+            state.bans = 0;
+            state.unauthorized.clear();
+        }
+    }
+
+    pub fn stats(&self, now: Instant) -> ConnectionStats {
+        let inner = self.inner.lock().unwrap();
+        let banned = inner
+            .nodes
+            .iter()
+            .filter_map(|(node, state)| {
+                let until = state.banned_until.filter(|until| *until > now)?;
+                Some((
+                    *node,
+                    BannedNode {
+                        remaining: until - now,
+                        bans: state.bans,
+                    },
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+        ConnectionStats {
+            active: inner.active,
+            banned,
+            ..inner.stats.clone()
+        }
+    }
+}
+
+/// Counts a connection as active until dropped
+#[derive(Debug)]
+pub struct Permit {
+    inner: Arc<Mutex<Inner>>,
+    node: Option<NodeId>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active -= 1;
+        if let Some(state) = self.node.and_then(|node| inner.nodes.get_mut(&node)) {
+            state.active -= 1;
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! How the daemon can be reached: discovery, relays and the local address.
+use std::{collections::BTreeSet, net::SocketAddr, path::PathBuf};
+
+use iroh_net::{
+    discovery::{pkarr::PkarrPublisher, ConcurrentDiscovery, Discovery},
+    key::SecretKey,
+};
+use munin_proto::{LocalDiscovery, RelayConfig};
+use serde::{Deserialize, Serialize};
+
+/// Network settings of the daemon, the `[network]` section of the config
+///
+/// Changes only apply after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Network {
+    /// How controllers find the daemon, none at all if empty
+    pub discovery: BTreeSet<DiscoveryMode>,
+    pub relay: RelayConfig,
+    /// Local address to bind to, any address and a random port if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_addr: Option<SocketAddr>,
+    /// Run a relay server for other nodes, needs the `relay` feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay_server: Option<RelayServer>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            discovery: [DiscoveryMode::N0Dns].into(),
+            relay: RelayConfig::Default,
+            bind_addr: None,
+            relay_server: None,
+        }
+    }
+}
+
+/// A relay server run by the daemon, the `[network.relay_server]` section
+///
+/// Nodes use it if it is in their `relay` list, as `http://<host>:<port>`,
+/// or `https://<host>` with `tls`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayServer {
+    /// Address to serve the relay on, without tls, e.g. `0.0.0.0:80`
+    pub http_bind_addr: SocketAddr,
+    /// Address of the STUN server that tells nodes their public address, e.g. `0.0.0.0:3478`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stun_bind_addr: Option<SocketAddr>,
+    /// Serve the relay with tls, the http address then only answers captive portal checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<RelayTls>,
+}
+
+/// The certificate of a relay server, e.g. from Let's Encrypt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayTls {
+    /// e.g. `0.0.0.0:443`
+    pub https_bind_addr: SocketAddr,
+    /// PEM file with the certificate chain
+    pub cert_path: PathBuf,
+    /// PEM file with the private key, not encrypted
+    pub key_path: PathBuf,
+}
+
+/// A way for controllers to find the addresses of the daemon by its node id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscoveryMode {
+    /// Publish to the DNS servers of number 0, needs internet access
+    N0Dns,
+    /// Answer queries on the local network, see [LocalDiscovery]
+    Local,
+}
+
+impl Network {
+    /// The discovery services to announce the daemon with, if any
+    ///
+    /// `name` returns the current name of the daemon, for local discovery.
+    pub fn discovery(
+        &self,
+        secret_key: &SecretKey,
+        name: impl Fn() -> String + Send + 'static,
+    ) -> anyhow::Result<Option<Box<dyn Discovery>>> {
+        let mut services: Vec<Box<dyn Discovery>> = Vec::new();
+        let mut name = Some(name);
+        for mode in &self.discovery {
+            match mode {
+                DiscoveryMode::N0Dns => {
+                    services.push(Box::new(PkarrPublisher::n0_dns(secret_key.clone())));
+                }
+                DiscoveryMode::Local => {
+                    let name = name.take().expect("modes are unique");
+                    services.push(Box::new(LocalDiscovery::announce(
+                        secret_key.clone(),
+                        name,
+                    )?));
+                }
+            }
+        }
+        Ok(match services.len() {
+            0 => None,
+            1 => services.pop(),
+            _ => Some(Box::new(ConcurrentDiscovery::from_services(services))),
+        })
+    }
+}
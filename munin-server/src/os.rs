@@ -1,9 +1,5 @@
 use std::{io, time::Duration};
 
-use bytes::Bytes;
-use cpal::traits::HostTrait;
-use rodio::{DeviceTrait, Source};
-
 pub fn list_processes() -> Vec<(u32, String)> {
     // Create a System object to get information about the system.
     let mut system = sysinfo::System::new_all();
@@ -134,9 +130,9 @@ pub fn kill_process_by_id(pid: u32) -> io::Result<()> {
 
         let res = unsafe { kill(pid as i32, SIGKILL) };
         if res == 0 {
-            return Ok(());
+            Ok(())
         } else {
-            return Err(io::Error::last_os_error());
+            Err(io::Error::last_os_error())
         }
     }
 
@@ -169,76 +165,3 @@ pub fn kill_process_by_id(pid: u32) -> io::Result<()> {
         return Err(io::Error::new(io::ErrorKind::Other, "Unsupported platform"));
     }
 }
-
-#[allow(dead_code)]
-pub fn play_audio_on_all_devices(audio_data: Bytes) -> anyhow::Result<Vec<String>> {
-    // Get the default host and all available output devices
-    let host = cpal::default_host();
-    let devices = host
-        .output_devices()
-        .expect("Failed to get output devices")
-        .collect::<Vec<_>>();
-
-    if devices.is_empty() {
-        eprintln!("No audio output devices found!");
-        return Ok(vec![]);
-    } else {
-        for device in &devices {
-            println!(
-                "Found output device: {}",
-                device
-                    .name()
-                    .unwrap_or_else(|_| "Unknown Device".to_string())
-            );
-        }
-    }
-
-    let mut results = Vec::new();
-    // Iterate over all output devices and play the sound
-    std::thread::scope(|scope| {
-        for device in devices {
-            let device_name = device
-                .name()
-                .unwrap_or_else(|_| "Unknown Device".to_string());
-            println!("Playing on: {}", device_name);
-
-            // Create a new output stream and stream handle for each device
-            if let Ok((_stream, stream_handle)) = rodio::OutputStream::try_from_device(&device) {
-                let audio_data = audio_data.clone();
-                results.push(format!("Playing sound on device: {}", device_name));
-                scope.spawn(move || {
-                    // Create a cursor for the embedded audio data
-                    let cursor = io::Cursor::new(audio_data);
-                    play_sound_on_device(cursor, &stream_handle);
-                });
-            } else {
-                results.push(format!(
-                    "Error: failed to create stream for device: {}",
-                    device_name
-                ));
-            }
-        }
-    });
-
-    Ok(results)
-}
-
-// Helper function to play the sound on a single device
-fn play_sound_on_device(audio_data: io::Cursor<Bytes>, stream_handle: &rodio::OutputStreamHandle) {
-    let sink = rodio::Sink::try_new(stream_handle).unwrap();
-    let source = rodio::Decoder::new(audio_data).unwrap();
-    let source = source.amplify(10.0);
-    sink.append(source);
-    sink.sleep_until_end(); // Block until the sound finishes playing
-}
-
-pub fn play_sound_on_default_device(audio_data: Bytes) -> anyhow::Result<()> {
-    let audio_data = io::Cursor::new(audio_data);
-    let (_stream, stream_handle) = rodio::OutputStream::try_default()?;
-    let sink = rodio::Sink::try_new(&stream_handle)?;
-    let source = rodio::Decoder::new(audio_data)?;
-    let source = source.amplify(10.0);
-    sink.append(source);
-    sink.sleep_until_end(); // Block until the sound finishes playing
-    Ok(())
-}
@@ -0,0 +1,102 @@
+//! One-time secrets that let a new controller add itself to the allowed nodes.
+//!
+//! Secrets are created by `munin-d pair` while the daemon may already be
+//! running, so they live in their own file instead of the config.
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use munin_proto::{PairingSecret, Role};
+use serde::{Deserialize, Serialize};
+
+use crate::config::write_atomic;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingPairing {
+    secret: PairingSecret,
+    role: Role,
+    expires: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct Pairings {
+    path: PathBuf,
+    /// Makes sure a secret is only redeemed once
+    lock: Mutex<()>,
+}
+
+impl Pairings {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Unexpired pairings
+    fn load(&self) -> anyhow::Result<Vec<PendingPairing>> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pairings = serde_json::from_slice::<Vec<PendingPairing>>(&data)?;
+        let now = SystemTime::now();
+        pairings.retain(|pairing| pairing.expires > now);
+        Ok(pairings)
+    }
+
+    fn save(&self, pairings: &[PendingPairing]) -> anyhow::Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec(pairings)?)
+    }
+
+    /// Create a new secret that grants `role` until it is used or `ttl` has passed
+    pub fn create(&self, role: Role, ttl: Duration) -> anyhow::Result<PairingSecret> {
+        let expires = SystemTime::now()
+            .checked_add(ttl)
+            .context("the time to live is too long")?;
+        let _guard = self.lock.lock().unwrap();
+        let mut pairings = self.load()?;
+        let secret = rand::random::<PairingSecret>();
+        pairings.push(PendingPairing {
+            secret,
+            role,
+            expires,
+        });
+        self.save(&pairings)?;
+        Ok(secret)
+    }
+
+    /// Use up a secret, returning the role it grants
+    pub fn redeem(&self, secret: &PairingSecret) -> anyhow::Result<Role> {
+        self.redeem_with(secret, Ok)
+    }
+
+    /// Call `f` with the role a secret grants, and use up the secret if `f` succeeds
+    ///
+    /// If `f` fails, e.g. because the config could not be saved, the secret
+    /// stays valid and the controller can try again.
+    pub fn redeem_with<T>(
+        &self,
+        secret: &PairingSecret,
+        f: impl FnOnce(Role) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let mut pairings = self.load()?;
+        let index = pairings
+            .iter()
+            .position(|pairing| constant_time_eq(&pairing.secret, secret))
+            .ok_or_else(|| anyhow::anyhow!("invalid or expired pairing secret"))?;
+        let result = f(pairings[index].role)?;
+        pairings.remove(index);
+        self.save(&pairings)?;
+        Ok(result)
+    }
+}
+
+fn constant_time_eq(a: &PairingSecret, b: &PairingSecret) -> bool {
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
@@ -0,0 +1,98 @@
+//! Processes that requests must never touch, no matter the role of the controller.
+use munin_proto::Error;
+use sysinfo::{Pid, ProcessesToUpdate, System};
+
+/// Process names protected on every daemon
+///
+/// Init systems, session managers and display servers, killing any of them
+/// takes down the machine or the session of its user.
+pub const BUILTIN_PROTECTED: &[&str] = &[
+    // linux
+    "init",
+    "systemd",
+    "systemd-logind",
+    "dbus-daemon",
+    "gdm",
+    "gdm3",
+    "sddm",
+    "lightdm",
+    "Xorg",
+    "Xwayland",
+    "gnome-shell",
+    "kwin_wayland",
+    "kwin_x11",
+    "plasmashell",
+    // macos
+    "launchd",
+    "kernel_task",
+    "loginwindow",
+    "WindowServer",
+    // windows
+    "System",
+    "smss",
+    "csrss",
+    "wininit",
+    "winlogon",
+    "services",
+    "lsass",
+    "dwm",
+    "explorer",
+    // ourselves, also under another pid, e.g. while being restarted
+    "munin-d",
+    "munin-service",
+];
+
+/// The protected pids and names of a daemon, see [BUILTIN_PROTECTED]
+#[derive(Debug, Clone)]
+pub struct ProtectedProcesses {
+    pids: Vec<u32>,
+    names: Vec<String>,
+}
+
+impl ProtectedProcesses {
+    /// The built-in list, plus process names from the daemon config
+    pub fn new(configured: &[String]) -> Self {
+        Self {
+            // the idle process on windows, init everywhere else, and the daemon itself
+            pids: vec![0, 1, std::process::id()],
+            names: BUILTIN_PROTECTED
+                .iter()
+                .map(|name| normalize(name))
+                .chain(configured.iter().map(|name| normalize(name)))
+                .collect(),
+        }
+    }
+
+    /// Fail with [Error::ProtectedProcess] if the process must not be touched
+    pub fn check(&self, pid: u32) -> Result<(), Error> {
+        let name = process_name(pid);
+        let protected = self.pids.contains(&pid)
+            || name
+                .as_ref()
+                .is_some_and(|name| self.names.contains(&normalize(name)));
+        if protected {
+            return Err(Error::ProtectedProcess {
+                pid,
+                name: name.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn process_name(pid: u32) -> Option<String> {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    let process = system.process(pid)?;
+    Some(process.name().to_string_lossy().into())
+}
+
+/// Compare names case insensitively and without `.exe`, so one list works on all platforms
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.strip_suffix(".exe") {
+        Some(name) => name.to_string(),
+        None => name,
+    }
+}
@@ -0,0 +1,72 @@
+//! A relay server next to the daemon, for nodes that can't reach each other directly.
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::Context;
+use iroh_net::{
+    key::SecretKey,
+    relay::server::{CertConfig, Limits, RelayConfig, Server, ServerConfig, StunConfig, TlsConfig},
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+use crate::network::{RelayServer, RelayTls};
+
+/// Start the relay server, it stops when the returned [Server] is dropped
+pub async fn spawn(config: &RelayServer) -> anyhow::Result<Server> {
+    let tls = match &config.tls {
+        Some(tls) => Some(load_tls(tls.clone()).await?),
+        None => None,
+    };
+    let config = ServerConfig::<std::io::Error> {
+        relay: Some(RelayConfig {
+            // clients don't pin the key of a relay, so a new one is fine
+            secret_key: SecretKey::generate(),
+            http_bind_addr: config.http_bind_addr,
+            tls,
+            limits: Limits::default(),
+        }),
+        stun: config
+            .stun_bind_addr
+            .map(|bind_addr| StunConfig { bind_addr }),
+        metrics_addr: None,
+    };
+    let server = Server::spawn(config).await?;
+    tracing::info!(
+        "Relay server listening on {:?}, https {:?}, stun {:?}",
+        server.http_addr(),
+        server.https_addr(),
+        server.stun_addr()
+    );
+    Ok(server)
+}
+
+async fn load_tls(tls: RelayTls) -> anyhow::Result<TlsConfig<std::io::Error>> {
+    let (private_key, certs) = tokio::task::spawn_blocking(move || {
+        anyhow::Ok((load_key(&tls.key_path)?, load_certs(&tls.cert_path)?))
+    })
+    .await??;
+    Ok(TlsConfig {
+        https_bind_addr: tls.https_bind_addr,
+        cert: CertConfig::Manual { private_key, certs },
+    })
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("cannot parse {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {}", path.display());
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("cannot parse {}", path.display()))?
+        .with_context(|| {
+            format!(
+                "no private key in {}, encrypted keys are not supported",
+                path.display()
+            )
+        })
+}
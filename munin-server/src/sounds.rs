@@ -0,0 +1,107 @@
+//! The catalog of sounds a daemon can play, by name.
+//!
+//! The catalog consists of the sounds embedded in the binary and the clips the
+//! user has dropped into the sounds directory of the daemon.
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use bytes::Bytes;
+use munin_proto::{normalize_sound_name, AudioSource, SoundInfo};
+
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("wakeup", include_bytes!("../assets/wake_up.mp3")),
+    ("alarm", include_bytes!("../assets/alarm.mp3")),
+    ("rickroll", include_bytes!("../assets/rickroll.mp3")),
+    ("gotobed", include_bytes!("../assets/go_to_bed.mp3")),
+];
+
+/// File extensions of user provided clips that we know how to decode
+const EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "flac"];
+
+#[derive(Debug, Clone)]
+pub struct Sound {
+    pub data: Bytes,
+    pub builtin: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SoundCatalog {
+    sounds: BTreeMap<String, Sound>,
+}
+
+impl SoundCatalog {
+    /// A catalog containing only the embedded sounds
+    pub fn builtin() -> Self {
+        let sounds = EMBEDDED
+            .iter()
+            .map(|(name, data)| {
+                let sound = Sound {
+                    data: Bytes::from_static(data),
+                    builtin: true,
+                };
+                (name.to_string(), sound)
+            })
+            .collect();
+        Self { sounds }
+    }
+
+    /// The embedded sounds plus all clips in `dir`
+    ///
+    /// A user provided clip with the same name as an embedded sound replaces it.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut catalog = Self::builtin();
+        if !dir.exists() {
+            return Ok(catalog);
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(ext) = path.extension().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            if !EXTENSIONS.contains(&ext.to_lowercase().as_str()) {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            let name = normalize_sound_name(stem);
+            if name.is_empty() {
+                continue;
+            }
+            let data = std::fs::read(&path)
+                .with_context(|| format!("reading sound {}", path.display()))?;
+            tracing::info!("Adding sound {} from {}", name, path.display());
+            let sound = Sound {
+                data: data.into(),
+                builtin: false,
+            };
+            catalog.sounds.insert(name, sound);
+        }
+        Ok(catalog)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Sound> {
+        self.sounds.get(&normalize_sound_name(name))
+    }
+
+    /// Get the audio data for a source
+    pub fn resolve(&self, source: &AudioSource) -> anyhow::Result<Bytes> {
+        match source {
+            AudioSource::Sound(name) => self
+                .get(name)
+                .map(|sound| sound.data.clone())
+                .ok_or_else(|| anyhow::anyhow!("Unknown sound: {}", name)),
+            AudioSource::Url(url) => anyhow::bail!("URL playback not implemented: {}", url),
+        }
+    }
+
+    pub fn list(&self) -> Vec<SoundInfo> {
+        self.sounds
+            .iter()
+            .map(|(name, sound)| SoundInfo {
+                name: name.clone(),
+                builtin: sound.builtin,
+            })
+            .collect()
+    }
+}
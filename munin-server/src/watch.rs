@@ -0,0 +1,38 @@
+//! Watching the config file, so edits apply without a restart.
+use std::{ffi::OsString, path::Path};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Watch the config file, sending `()` whenever it may have changed
+///
+/// Watches the directory instead of the file, because editors and
+/// [crate::Config::save] replace the file instead of writing it in place.
+/// Changes stop being reported once the watcher is dropped.
+pub(crate) fn watch_config(
+    path: &Path,
+) -> anyhow::Result<(RecommendedWatcher, UnboundedReceiver<()>)> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", path.display()))?;
+    let name = path.file_name().map(OsString::from);
+    let (send, recv) = unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                let modified =
+                    event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove();
+                if modified
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == name.as_deref())
+                {
+                    send.send(()).ok();
+                }
+            }
+            Err(e) => tracing::warn!("Error watching the config: {}", e),
+        })?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok((watcher, recv))
+}
@@ -0,0 +1,47 @@
+use iroh_net::key::SecretKey;
+use munin_server::AccessRequests;
+
+#[test]
+fn test_queue() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let access = AccessRequests::new(dir.path().join("access_requests.json"));
+    let alice = SecretKey::generate().public();
+    let bob = SecretKey::generate().public();
+
+    access.record(alice, None)?;
+    let request = access.record(alice, Some("alice\n laptop".into()))?;
+    assert_eq!(request.attempts, 2);
+    assert_eq!(request.name.as_deref(), Some("alice laptop"));
+    // later attempts without a name keep the one we have
+    let request = access.record(alice, None)?;
+    assert_eq!(request.name.as_deref(), Some("alice laptop"));
+
+    access.record(bob, None)?;
+    access.deny(bob)?;
+    assert!(access.record(bob, None)?.denied);
+
+    access.remove(alice)?;
+    assert!(access.remove(alice).is_err());
+    let nodes = access
+        .list()?
+        .into_iter()
+        .map(|r| r.node)
+        .collect::<Vec<_>>();
+    assert_eq!(nodes, vec![bob]);
+    Ok(())
+}
+
+#[test]
+fn test_cap() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let access = AccessRequests::new(dir.path().join("access_requests.json"));
+    let first = SecretKey::generate().public();
+    access.record(first, None)?;
+    for _ in 0..AccessRequests::MAX_PENDING {
+        access.record(SecretKey::generate().public(), None)?;
+    }
+    let requests = access.list()?;
+    assert_eq!(requests.len(), AccessRequests::MAX_PENDING);
+    assert!(requests.iter().all(|r| r.node != first));
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use chrono::{Local, TimeZone};
+use iroh_net::key::SecretKey;
+use munin_proto::{AlarmSpec, AudioOutput, AudioSource, TimeOfDay, Weekdays};
+use munin_server::{is_due, Config};
+
+fn spec(at: &str, days: &str) -> AlarmSpec {
+    AlarmSpec {
+        at: at.parse().unwrap(),
+        days: days.parse().unwrap(),
+        source: AudioSource::Sound("wakeup".to_string()),
+        volume: None,
+        output: AudioOutput::Default,
+    }
+}
+
+#[test]
+fn test_parse() {
+    let at = "7:05".parse::<TimeOfDay>().unwrap();
+    assert_eq!(at, TimeOfDay { hour: 7, minute: 5 });
+    assert_eq!(at.to_string(), "07:05");
+    assert!("23:59".parse::<TimeOfDay>().is_ok());
+    for invalid in ["24:00", "12:60", "1230", "ab:cd", "-1:30", ""] {
+        assert!(invalid.parse::<TimeOfDay>().is_err(), "{}", invalid);
+    }
+
+    assert_eq!("daily".parse::<Weekdays>(), Ok(Weekdays::EVERY_DAY));
+    assert_eq!("Weekdays".parse::<Weekdays>(), Ok(Weekdays::WEEKDAYS));
+    assert_eq!("weekends".parse::<Weekdays>(), Ok(Weekdays::WEEKEND));
+    let days = "Monday, wed".parse::<Weekdays>().unwrap();
+    assert!(days.contains(0) && days.contains(2) && !days.contains(1));
+    assert_eq!(days.to_string(), "mon,wed");
+    assert_eq!("sat,sun".parse::<Weekdays>(), Ok(Weekdays::WEEKEND));
+    assert_eq!(Weekdays::WEEKEND.to_string(), "weekend");
+    assert!("funday".parse::<Weekdays>().is_err());
+    assert!("".parse::<Weekdays>().is_err());
+    assert!(!Weekdays::EVERY_DAY.contains(7));
+}
+
+#[test]
+fn test_is_due() {
+    // 2024-10-07 is a monday
+    let monday = Local.with_ymd_and_hms(2024, 10, 7, 7, 30, 0).unwrap();
+    let saturday = Local.with_ymd_and_hms(2024, 10, 12, 7, 30, 0).unwrap();
+    let later = Local.with_ymd_and_hms(2024, 10, 7, 7, 31, 0).unwrap();
+    let spec = spec("07:30", "weekdays");
+    assert!(is_due(&spec, &monday));
+    assert!(!is_due(&spec, &saturday));
+    assert!(!is_due(&spec, &later));
+    assert!(is_due(&self::spec("07:30", "sat"), &saturday));
+}
+
+#[test]
+fn test_alarm_ids() -> anyhow::Result<()> {
+    let mut config = Config::new(SecretKey::generate());
+    let first = config.add_alarm(spec("07:30", "daily"))?;
+    let second = config.add_alarm(spec("08:00", "daily"))?;
+    config.remove_alarm(second)?;
+    // a new alarm does not get the id of the removed one
+    let third = config.add_alarm(spec("09:00", "daily"))?;
+    assert!(first < second && second < third);
+    assert!(config.remove_alarm(second).is_err());
+    assert_eq!(config.alarms.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_alarm_ids_exhausted() {
+    let mut config = Config::new(SecretKey::generate());
+    config.next_alarm_id = u32::MAX;
+    assert!(config.add_alarm(spec("07:30", "daily")).is_err());
+    assert!(config.alarms.is_empty());
+}
@@ -1,8 +1,192 @@
-use std::io::Cursor;
+use std::{collections::BTreeSet, io::Cursor};
 
+use munin_proto::{AudioOutput, AudioSource, PlayAudioRequest, PlaybackStatus};
+use munin_server::{play_audio, unique_device_names, AudioPlayer, NullBackend, SoundCatalog};
 use rodio::{Decoder, OutputStream, Sink};
 
+fn request(name: &str, volume: f32, output: AudioOutput) -> PlayAudioRequest {
+    PlayAudioRequest {
+        source: AudioSource::Sound(name.to_string()),
+        volume: Some(volume),
+        wait: true,
+        output,
+    }
+}
+
+#[test]
+fn test_builtin_catalog() {
+    let catalog = SoundCatalog::builtin();
+    let names = catalog
+        .list()
+        .into_iter()
+        .map(|sound| sound.name)
+        .collect::<BTreeSet<_>>();
+    let expected = munin_proto::BUILTIN_SOUNDS
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(names, expected);
+}
+
+#[tokio::test]
+async fn test_all_sounds_decode() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join("Bed Time.mp3"),
+        include_bytes!("../assets/go_to_bed.mp3"),
+    )?;
+    std::fs::write(dir.path().join("ignored.txt"), b"not a sound")?;
+    let catalog = SoundCatalog::load(dir.path())?;
+    let user = catalog
+        .list()
+        .into_iter()
+        .filter(|sound| !sound.builtin)
+        .map(|sound| sound.name)
+        .collect::<Vec<_>>();
+    assert_eq!(user, vec!["bedtime".to_string()]);
+
+    let backend = NullBackend::default();
+    let player = AudioPlayer::spawn_with({
+        let backend = backend.clone();
+        move || backend
+    })?;
+    let sounds = catalog.list();
+    for sound in &sounds {
+        let results = play_audio(
+            &catalog,
+            &player,
+            request(&sound.name, 1.0, AudioOutput::Default),
+        )
+        .await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].result, Ok(PlaybackStatus::Finished));
+    }
+    let played = backend.played();
+    assert_eq!(played.len(), sounds.len());
+    for clip in played {
+        assert!(clip.samples > 0);
+        assert!(!clip.duration.is_zero());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_volume() -> anyhow::Result<()> {
+    let catalog = SoundCatalog::builtin();
+    let backend = NullBackend::default();
+    let player = AudioPlayer::spawn_with({
+        let backend = backend.clone();
+        move || backend
+    })?;
+    for volume in [0.5, 0.25] {
+        play_audio(
+            &catalog,
+            &player,
+            request("alarm", volume, AudioOutput::Default),
+        )
+        .await?;
+    }
+    let played = backend.played();
+    assert_eq!(played[0].volume, 0.5);
+    assert_eq!(played[1].volume, 0.25);
+    let ratio = played[1].peak / played[0].peak;
+    assert!((ratio - 0.5).abs() < 0.01, "ratio {}", ratio);
+
+    // out of range volumes are rejected before anything is played
+    let res = play_audio(
+        &catalog,
+        &player,
+        request("alarm", 100.0, AudioOutput::Default),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(backend.played().len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_queue_and_devices() -> anyhow::Result<()> {
+    let catalog = SoundCatalog::builtin();
+    let backend = NullBackend::with_devices(["speakers", "headset"]);
+    let player = AudioPlayer::spawn_with({
+        let backend = backend.clone();
+        move || backend
+    })?;
+
+    // without waiting, clips are reported as queued and played in order
+    for name in ["wakeup", "alarm"] {
+        let mut request = request(name, 1.0, AudioOutput::Default);
+        request.wait = false;
+        let results = play_audio(&catalog, &player, request).await?;
+        assert_eq!(results[0].result, Ok(PlaybackStatus::Queued));
+    }
+    let results = play_audio(&catalog, &player, request("gotobed", 1.0, AudioOutput::All)).await?;
+    let devices = results
+        .iter()
+        .map(|r| r.device.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(devices, vec!["speakers", "headset"]);
+    let results = play_audio(
+        &catalog,
+        &player,
+        request("alarm", 1.0, AudioOutput::Device("HEADSET".into())),
+    )
+    .await?;
+    assert_eq!(results[0].device, "headset");
+
+    let played = backend
+        .played()
+        .into_iter()
+        .map(|clip| clip.device)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        played,
+        vec!["speakers", "speakers", "speakers", "headset", "headset"]
+    );
+
+    let res = play_audio(
+        &catalog,
+        &player,
+        request("alarm", 1.0, AudioOutput::Device("hdmi".into())),
+    )
+    .await;
+    assert!(res.is_err());
+    let res = play_audio(
+        &catalog,
+        &player,
+        request("nosuchsound", 1.0, AudioOutput::All),
+    )
+    .await;
+    assert!(res.is_err());
+    player.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_unique_device_names() {
+    let names = [
+        "Speakers",
+        "Unknown Device",
+        "Speakers",
+        "Unknown Device",
+        "Speakers #2",
+    ];
+    let names = unique_device_names(names.map(String::from));
+    assert_eq!(
+        names,
+        vec![
+            "Speakers",
+            "Unknown Device",
+            "Speakers #2",
+            "Unknown Device #2",
+            "Speakers #2 #2"
+        ]
+    );
+}
+
+/// Plays on the real default output device, run with `--ignored` on a machine with speakers
 #[test]
+#[ignore]
 fn test_audio() {
     // Open a stream to the default output device
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
@@ -0,0 +1,139 @@
+use std::{
+    io::Write,
+    time::{Duration, SystemTime},
+};
+
+use iroh_net::key::SecretKey;
+use munin_proto::{AuditEntry, AuditOutcome, AuditQuery, Error};
+use munin_server::AuditLog;
+
+fn entry(node: &SecretKey, time: SystemTime, request: &str, outcome: AuditOutcome) -> AuditEntry {
+    AuditEntry {
+        time,
+        node: node.public(),
+        request: request.to_string(),
+        outcome,
+    }
+}
+
+#[tokio::test]
+async fn test_query() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let log = AuditLog::new(dir.path().join("audit.jsonl"))?;
+    let alice = SecretKey::generate();
+    let bob = SecretKey::generate();
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let minute = Duration::from_secs(60);
+    log.record(&entry(&alice, t0, "ListProcesses", AuditOutcome::Ok));
+    log.record(&entry(
+        &bob,
+        t0 + minute,
+        "Shutdown",
+        AuditOutcome::Error(Error::Failed("nope".into())),
+    ));
+    log.record(&entry(
+        &bob,
+        t0 + 2 * minute,
+        "Connect",
+        AuditOutcome::Unauthorized,
+    ));
+
+    let all = log.query(&AuditQuery::default()).await?;
+    assert_eq!(all.len(), 3);
+    assert_eq!(
+        all[1].outcome,
+        AuditOutcome::Error(Error::Failed("nope".into()))
+    );
+
+    let by_bob = log
+        .query(&AuditQuery {
+            node: Some(bob.public()),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(by_bob.len(), 2);
+
+    let window = log
+        .query(&AuditQuery {
+            since: Some(t0 + minute),
+            until: Some(t0 + minute),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(window.len(), 1);
+    assert_eq!(window[0].request, "Shutdown");
+
+    // the limit keeps the most recent entries
+    let last = log
+        .query(&AuditQuery {
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(last[0].request, "Connect");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rotation() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("audit.jsonl");
+    let log = AuditLog::with_max_size(&path, 1024)?;
+    let node = SecretKey::generate();
+    for i in 0..100 {
+        log.record(&entry(
+            &node,
+            SystemTime::now(),
+            &format!("KillProcess({})", i),
+            AuditOutcome::Ok,
+        ));
+    }
+    let entries = log.query(&AuditQuery::default()).await?;
+    assert!(entries.len() < 100);
+    // only the current and the previous file are kept
+    let files = std::fs::read_dir(dir.path())?.count();
+    assert_eq!(files, 2);
+    assert!(std::fs::metadata(&path)?.len() < 2048);
+    assert_eq!(entries.last().unwrap().request, "KillProcess(99)");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_response_size() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("audit.jsonl");
+    let log = AuditLog::with_max_size(&path, 16 * 1024 * 1024)?;
+    let node = SecretKey::generate();
+    let long = "x".repeat(munin_proto::MAX_REQUEST_SIZE);
+
+    // long requests and errors are cut off when recorded
+    log.record(&entry(
+        &node,
+        SystemTime::now(),
+        &long,
+        AuditOutcome::Error(Error::Failed(long.clone())),
+    ));
+    let entries = log.query(&AuditQuery::default()).await?;
+    assert!(entries[0].request.len() < 300);
+    let AuditOutcome::Error(Error::Failed(message)) = &entries[0].outcome else {
+        panic!("unexpected outcome {:?}", entries[0].outcome);
+    };
+    assert!(message.len() < 300);
+
+    // entries written before that still fit into a response
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+    for i in 0..600 {
+        let entry = entry(
+            &node,
+            SystemTime::now(),
+            &format!("{}{}", long, i),
+            AuditOutcome::Ok,
+        );
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    }
+    let entries = log.query(&AuditQuery::default()).await?;
+    let response = munin_proto::AuditLogResponse { entries };
+    assert!(postcard::to_allocvec(&response)?.len() < munin_proto::MAX_RESPONSE_SIZE);
+    assert!(response.entries.last().unwrap().request.ends_with("599"));
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Running a daemon in a test, in a temporary data dir.
+// each test binary only uses some of the helpers
+#![allow(dead_code)]
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use iroh_net::{relay::RelayMode, Endpoint, NodeAddr};
+use munin_proto::{AuditEntry, AuditQuery, RelayConfig};
+use munin_server::{AuditLog, Config, Control, Network};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+
+pub struct Daemon {
+    /// The config the daemon was started with, as saved in the data dir
+    pub config: Config,
+    pub addr: NodeAddr,
+    pub control: UnboundedSender<Control>,
+    pub task: JoinHandle<anyhow::Result<()>>,
+    _dir: tempfile::TempDir,
+}
+
+/// Start a daemon with `config` in a new data dir, only reachable on localhost
+///
+/// The data dir is passed in `MUNIN_DATA_DIR`, so there can only be one
+/// daemon per test binary.
+pub fn start(mut config: Config) -> anyhow::Result<Daemon> {
+    let dir = tempfile::tempdir()?;
+    std::env::set_var("MUNIN_DATA_DIR", dir.path());
+    let port = UdpSocket::bind("127.0.0.1:0")?.local_addr()?.port();
+    let bind_addr = SocketAddr::from(([127, 0, 0, 1], port));
+    config.network = Network {
+        discovery: Default::default(),
+        relay: RelayConfig::Disabled,
+        bind_addr: Some(bind_addr),
+        relay_server: None,
+    };
+    config.save()?;
+    let addr = NodeAddr::new(config.secret_key.public()).with_direct_addresses([bind_addr]);
+    let (control, recv) = unbounded_channel();
+    let task = tokio::spawn(munin_server::run(config.clone(), recv));
+    Ok(Daemon {
+        config,
+        addr,
+        control,
+        task,
+        _dir: dir,
+    })
+}
+
+/// An endpoint for connecting to the daemon directly
+pub async fn client() -> anyhow::Result<Endpoint> {
+    Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .bind()
+        .await
+}
+
+/// Wait for the daemon to record a matching entry in its audit log
+pub async fn audit_entry(f: impl Fn(&AuditEntry) -> bool) -> anyhow::Result<AuditEntry> {
+    let log = AuditLog::new(Config::audit_log_path()?)?;
+    for _ in 0..100 {
+        let entries = log.query(&AuditQuery::default()).await?;
+        if let Some(entry) = entries.into_iter().find(&f) {
+            return Ok(entry);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("no matching audit log entry")
+}
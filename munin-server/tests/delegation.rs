@@ -0,0 +1,97 @@
+use std::time::{Duration, SystemTime};
+
+use iroh_net::key::SecretKey;
+use munin_proto::{Delegation, DelegationChain, RequestKind, Role};
+use munin_server::Config;
+
+fn config(owner: &SecretKey) -> Config {
+    Config {
+        owner: Some(owner.public()),
+        ..Config::new(SecretKey::generate())
+    }
+}
+
+fn delegate(
+    issuer: &SecretKey,
+    controller: &SecretKey,
+    role: Role,
+    expires: SystemTime,
+) -> Delegation {
+    Delegation {
+        issuer: issuer.public(),
+        controller: controller.public(),
+        role,
+        expires,
+        requests: None,
+    }
+}
+
+#[test]
+fn test_chain() -> anyhow::Result<()> {
+    let owner = SecretKey::generate();
+    let laptop = SecretKey::generate();
+    let phone = SecretKey::generate();
+    let config = config(&owner);
+    let now = SystemTime::now();
+    let day = Duration::from_secs(86400);
+
+    let to_laptop = delegate(&owner, &laptop, Role::Admin, now + 30 * day).sign(&owner);
+    let chain = DelegationChain(vec![to_laptop.clone()]);
+    let grant = config
+        .grant_for(laptop.public(), Some(&chain), now)?
+        .unwrap();
+    assert_eq!(grant.role, Role::Admin);
+
+    // the laptop passes on less than it has
+    let mut to_phone = delegate(&laptop, &phone, Role::Operator, now + 60 * day);
+    to_phone.requests = Some([RequestKind::PlayAudio].into());
+    let chain = DelegationChain(vec![to_laptop.clone(), to_phone.sign(&laptop)]);
+    let grant = config
+        .grant_for(phone.public(), Some(&chain), now)?
+        .unwrap();
+    assert_eq!(grant.role, Role::Operator);
+    assert_eq!(grant.expires, Some(now + 30 * day));
+    assert!(!grant.permits(RequestKind::KillProcess));
+
+    // not for someone else, and not after it expired
+    assert!(config
+        .grant_for(laptop.public(), Some(&chain), now)
+        .is_err());
+    assert!(config
+        .grant_for(phone.public(), Some(&chain), now + 31 * day)
+        .is_err());
+    // no delegation, no access
+    assert_eq!(config.grant_for(phone.public(), None, now)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_forged() -> anyhow::Result<()> {
+    let owner = SecretKey::generate();
+    let mallory = SecretKey::generate();
+    let config = config(&owner);
+    let now = SystemTime::now();
+    let expires = now + Duration::from_secs(3600);
+
+    // signed by mallory, claiming to be from the owner
+    let mut cert = delegate(&mallory, &mallory, Role::Admin, expires).sign(&mallory);
+    cert.delegation.issuer = owner.public();
+    let chain = DelegationChain(vec![cert]);
+    assert!(config
+        .grant_for(mallory.public(), Some(&chain), now)
+        .is_err());
+
+    // a valid cert, but with a higher role than was signed
+    let mut cert = delegate(&owner, &mallory, Role::Viewer, expires).sign(&owner);
+    cert.delegation.role = Role::Admin;
+    let chain = DelegationChain(vec![cert]);
+    assert!(config
+        .grant_for(mallory.public(), Some(&chain), now)
+        .is_err());
+
+    // the chain survives being sent around as text
+    let cert = delegate(&owner, &mallory, Role::Viewer, expires).sign(&owner);
+    let chain = DelegationChain(vec![cert]);
+    assert_eq!(chain.to_string().parse::<DelegationChain>()?, chain);
+    Ok(())
+}
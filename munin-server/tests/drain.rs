@@ -0,0 +1,25 @@
+use std::time::{Duration, Instant};
+
+use iroh_net::key::SecretKey;
+use munin_server::{Config, Control};
+
+mod common;
+
+#[tokio::test]
+async fn test_drain_then_abort() -> anyhow::Result<()> {
+    let mut config = Config::new(SecretKey::generate());
+    // the connection below waits for its request much longer than the daemon drains
+    config.timeouts.read_secs = 60;
+    config.timeouts.drain_secs = 1;
+    let daemon = common::start(config)?;
+    let client = common::client().await?;
+    let connection = client.connect(daemon.addr, munin_proto::ALPN).await?;
+
+    let shutdown = Instant::now();
+    daemon.control.send(Control::Shutdown)?;
+    daemon.task.await??;
+    assert!(shutdown.elapsed() < Duration::from_secs(10));
+    common::audit_entry(|entry| entry.request == "DaemonShutdown { aborted: 1 }").await?;
+    tokio::time::timeout(Duration::from_secs(5), connection.closed()).await?;
+    Ok(())
+}
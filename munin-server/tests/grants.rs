@@ -0,0 +1,86 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use iroh_net::key::SecretKey;
+use munin_proto::{Grant, RequestKind, Role};
+use munin_server::Config;
+
+fn config(admin: &SecretKey) -> Config {
+    Config {
+        allowed_nodes: BTreeMap::from([(admin.public(), Role::Admin.into())]),
+        ..Config::new(SecretKey::generate())
+    }
+}
+
+#[test]
+fn test_prune_expired() -> anyhow::Result<()> {
+    let admin = SecretKey::generate();
+    let guest = SecretKey::generate();
+    let mut config = config(&admin);
+    let now = SystemTime::now();
+    config.allow_node(
+        guest.public(),
+        Grant {
+            role: Role::Operator,
+            expires: Some(now + Duration::from_secs(60)),
+            requests: None,
+        },
+    )?;
+    assert!(config.prune_expired(now).is_empty());
+    let expired = config.prune_expired(now + Duration::from_secs(60));
+    assert_eq!(expired, vec![guest.public()]);
+    assert_eq!(config.allowed_nodes.len(), 1);
+
+    // granting something that has already expired is most likely a mistake
+    let res = config.allow_node(
+        guest.public(),
+        Grant {
+            role: Role::Operator,
+            expires: Some(now - Duration::from_secs(1)),
+            requests: None,
+        },
+    );
+    assert!(res.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_limited_admins() -> anyhow::Result<()> {
+    let admin = SecretKey::generate();
+    let mut config = config(&admin);
+    // a limited grant can't replace the last permanent admin
+    let limited = Grant {
+        role: Role::Admin,
+        expires: None,
+        requests: Some([RequestKind::ListAllowedNodes].into()),
+    };
+    assert!(config.allow_node(admin.public(), limited.clone()).is_err());
+    assert_eq!(config.allowed_nodes[&admin.public()], Role::Admin.into());
+
+    assert!(limited.permits(RequestKind::ListAllowedNodes));
+    assert!(limited.permits(RequestKind::GetVersion));
+    assert!(!limited.permits(RequestKind::Shutdown));
+    Ok(())
+}
+
+#[test]
+fn test_enroll_without_admin() -> anyhow::Result<()> {
+    // a fresh daemon has neither an owner nor an admin
+    let mut config = Config::new(SecretKey::generate());
+    let viewer = SecretKey::generate().public();
+    config.allow_node(viewer, Role::Viewer)?;
+    config.allow_node(viewer, Role::Operator)?;
+    assert_eq!(config.allowed_nodes[&viewer], Role::Operator.into());
+    config.disallow_node(viewer)?;
+    assert!(config.allowed_nodes.is_empty());
+
+    // once there is an admin, it can't be demoted or removed
+    let admin = SecretKey::generate().public();
+    config.allow_node(admin, Role::Admin)?;
+    assert!(config.allow_node(admin, Role::Viewer).is_err());
+    assert!(config.disallow_node(admin).is_err());
+    assert_eq!(config.allowed_nodes[&admin], Role::Admin.into());
+    Ok(())
+}
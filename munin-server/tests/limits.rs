@@ -0,0 +1,164 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use iroh_net::key::SecretKey;
+use munin_server::{Config, ConnectionLimiter, Limits, Rejection};
+
+#[test]
+fn test_limits() {
+    let limiter = ConnectionLimiter::new(Limits {
+        max_connections: 3,
+        max_connections_per_node: 2,
+        ..Default::default()
+    });
+    let addr = "127.0.0.1:1234".parse().unwrap();
+    let node = SecretKey::generate().public();
+    let now = Instant::now();
+
+    let mut a = limiter.admit(addr, now).unwrap();
+    let mut b = limiter.admit(addr, now).unwrap();
+    let mut c = limiter.admit(addr, now).unwrap();
+    assert_eq!(limiter.admit(addr, now).unwrap_err(), Rejection::Busy);
+    limiter.admit_node(&mut a, node, None, now).unwrap();
+    limiter.admit_node(&mut b, node, None, now).unwrap();
+    assert_eq!(
+        limiter.admit_node(&mut c, node, None, now).unwrap_err(),
+        Rejection::NodeLimit
+    );
+
+    // permits are released when the connections are done
+    drop((a, b, c));
+    let stats = limiter.stats(now);
+    assert_eq!(stats.active, 0);
+    assert_eq!(stats.accepted, 3);
+    assert_eq!(stats.refused, 1);
+    assert_eq!(stats.refused_node, 1);
+    let mut d = limiter.admit(addr, now).unwrap();
+    limiter.admit_node(&mut d, node, None, now).unwrap();
+}
+
+#[test]
+fn test_ban_backoff() {
+    let limiter = ConnectionLimiter::new(Limits {
+        unauthorized_per_minute: 2,
+        ban_secs: 10,
+        max_ban_secs: 30,
+        ..Default::default()
+    });
+    let addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+    let node = SecretKey::generate().public();
+    let mut now = Instant::now();
+
+    for ban in [10, 20, 30, 30] {
+        limiter.unauthorized(node, &[addr.ip()], now);
+        assert!(limiter.admit(addr, now).is_ok());
+        limiter.unauthorized(node, &[addr.ip()], now);
+        // banned by address before the handshake, also from another port
+        assert_eq!(limiter.admit(addr, now).unwrap_err(), Rejection::Banned);
+        let port = "127.0.0.1:4321".parse().unwrap();
+        assert_eq!(limiter.admit(port, now).unwrap_err(), Rejection::Banned);
+        // and by node id after the handshake
+        let other = "127.0.0.2:4321".parse().unwrap();
+        let mut permit = limiter.admit(other, now).unwrap();
+        assert_eq!(
+            limiter
+                .admit_node(&mut permit, node, None, now)
+                .unwrap_err(),
+            Rejection::Banned
+        );
+        assert_eq!(
+            limiter.stats(now).banned[&node].remaining,
+            Duration::from_secs(ban)
+        );
+        now += Duration::from_secs(ban);
+        assert!(limiter.admit(addr, now).is_ok());
+    }
+    assert_eq!(limiter.stats(now).bans, 4);
+}
+
+#[test]
+fn test_ban_direct_ip() {
+    let limiter = ConnectionLimiter::new(Limits {
+        unauthorized_per_minute: 1,
+        ..Default::default()
+    });
+    let mapped: SocketAddr = "[fd15:70a:510b::1]:12345".parse().unwrap();
+    let direct: IpAddr = "192.0.2.1".parse().unwrap();
+    let now = Instant::now();
+    limiter.unauthorized(SecretKey::generate().public(), &[mapped.ip(), direct], now);
+
+    // a new key from the same address is refused once the address is known
+    let fresh = "[fd15:70a:510b::2]:12345".parse().unwrap();
+    let mut permit = limiter.admit(fresh, now).unwrap();
+    let node = SecretKey::generate().public();
+    assert_eq!(
+        limiter
+            .admit_node(&mut permit, node, Some(direct), now)
+            .unwrap_err(),
+        Rejection::Banned
+    );
+    // but not through a relay, which many nodes share
+    let mut permit = limiter.admit(fresh, now).unwrap();
+    assert!(limiter.admit_node(&mut permit, node, None, now).is_ok());
+}
+
+#[test]
+fn test_access_requests_per_ip() {
+    let limiter = ConnectionLimiter::new(Limits {
+        access_requests_per_hour: 2,
+        ..Default::default()
+    });
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    let now = Instant::now();
+    assert!(limiter.new_access_request(Some(ip), now));
+    assert!(limiter.new_access_request(Some(ip), now));
+    assert!(!limiter.new_access_request(Some(ip), now));
+    // relayed nodes share one allowance, other addresses have their own
+    assert!(limiter.new_access_request(None, now));
+    assert!(limiter.new_access_request(Some("192.0.2.2".parse().unwrap()), now));
+    let later = now + Duration::from_secs(60 * 60);
+    assert!(limiter.new_access_request(Some(ip), later));
+}
+
+#[test]
+fn test_set_limits() {
+    let limiter = ConnectionLimiter::new(Limits {
+        max_connections: 1,
+        ..Default::default()
+    });
+    let addr = "127.0.0.1:1234".parse().unwrap();
+    let now = Instant::now();
+
+    let a = limiter.admit(addr, now).unwrap();
+    assert_eq!(limiter.admit(addr, now).unwrap_err(), Rejection::Busy);
+    // connections in flight are kept when the limits change
+    limiter.set_limits(Limits {
+        max_connections: 2,
+        ..Default::default()
+    });
+    let b = limiter.admit(addr, now).unwrap();
+    assert_eq!(limiter.stats(now).active, 2);
+    drop((a, b));
+}
+
+#[test]
+fn test_validate() {
+    let mut config = Config::new(SecretKey::generate());
+    assert!(config.validate().is_ok());
+    config.limits.ban_secs = config.limits.max_ban_secs + 1;
+    assert!(config.validate().is_err());
+    config.limits = Limits::default();
+    config.timeouts.read_secs = 0;
+    assert!(config.validate().is_err());
+    config.timeouts = Default::default();
+
+    assert!(config.set_name("den-pc".to_string()).is_ok());
+    assert!(config.set_name(" ".to_string()).is_err());
+    assert!(config.set_name("den\npc".to_string()).is_err());
+    assert!(config.set_name("x".repeat(65)).is_err());
+    assert_eq!(config.name, "den-pc");
+    config.name = String::new();
+    assert!(config.validate().is_err());
+}
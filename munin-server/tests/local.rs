@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use iroh_net::{discovery::Discovery, key::SecretKey, AddrInfo};
+use munin_proto::{query_local, LocalDiscovery};
+
+#[tokio::test]
+async fn test_local_discovery() -> anyhow::Result<()> {
+    let key = SecretKey::generate();
+    let discovery = LocalDiscovery::announce(key.clone(), || "minipc".to_string())?;
+    let addr_info = AddrInfo {
+        relay_url: None,
+        direct_addresses: ["192.168.1.20:41234".parse()?].into(),
+    };
+    discovery.publish(&addr_info);
+
+    let mut found = None;
+    query_local(Duration::from_secs(3), |announcement| {
+        if announcement.node_id == key.public() {
+            found = Some(announcement);
+        }
+        found.is_some()
+    })
+    .await?;
+    let found = found.expect("announcement not found");
+    assert_eq!(found.name, "minipc");
+    assert_eq!(found.addr_info, addr_info);
+    Ok(())
+}
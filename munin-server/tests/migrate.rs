@@ -0,0 +1,45 @@
+use iroh_net::key::SecretKey;
+use munin_proto::Role;
+use munin_server::Config;
+
+/// Only one test in this file, the data dir is set for the whole process
+#[test]
+fn test_migrate() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::env::set_var("MUNIN_DATA_DIR", dir.path());
+    let path = Config::default_path()?;
+    let secret_key = SecretKey::generate();
+    let admin = SecretKey::generate().public();
+
+    // a config from before there were versions and roles
+    let old = format!(
+        "name = \"minipc\"\nsecret_key = \"{}\"\nallowed_nodes = [\"{}\"]\n",
+        secret_key, admin
+    );
+    std::fs::write(&path, &old)?;
+    let config = Config::load()?;
+    assert_eq!(config.secret_key.to_bytes(), secret_key.to_bytes());
+    assert_eq!(config.allowed_nodes[&admin].role, Role::Admin);
+    assert_eq!(
+        std::fs::read_to_string(path.with_extension("toml.v0.bak"))?,
+        old
+    );
+    assert!(std::fs::read_to_string(&path)?.contains("version = 1"));
+    assert_eq!(Config::load()?, config);
+
+    // a config that can't be upgraded is left alone
+    let broken = format!(
+        "secret_key = \"{}\"\nallowed_nodes = [\"not a node id\"]\n",
+        secret_key
+    );
+    std::fs::write(&path, &broken)?;
+    assert!(Config::load().is_err());
+    assert_eq!(std::fs::read_to_string(&path)?, broken);
+
+    // and so is one from the future
+    let newer = format!("version = 1000\nsecret_key = \"{}\"\n", secret_key);
+    std::fs::write(&path, &newer)?;
+    assert!(Config::load().is_err());
+    assert_eq!(std::fs::read_to_string(&path)?, newer);
+    Ok(())
+}
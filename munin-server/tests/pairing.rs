@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use munin_proto::Role;
+use munin_server::Pairings;
+
+#[test]
+fn test_redeem_once() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let pairings = Pairings::new(dir.path().join("pairings.json"));
+    let viewer = pairings.create(Role::Viewer, Duration::from_secs(60))?;
+    let admin = pairings.create(Role::Admin, Duration::from_secs(60))?;
+    assert_eq!(pairings.redeem(&admin)?, Role::Admin);
+    assert!(pairings.redeem(&admin).is_err());
+    assert!(pairings
+        .redeem(&[0u8; munin_proto::PAIRING_SECRET_LEN])
+        .is_err());
+    // a second handle sees the same file, like `munin-d pair` and the daemon
+    let other = Pairings::new(dir.path().join("pairings.json"));
+    assert_eq!(other.redeem(&viewer)?, Role::Viewer);
+    Ok(())
+}
+
+#[test]
+fn test_expiry() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let pairings = Pairings::new(dir.path().join("pairings.json"));
+    let secret = pairings.create(Role::Operator, Duration::ZERO)?;
+    assert!(pairings.redeem(&secret).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_failed_redeem() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let pairings = Pairings::new(dir.path().join("pairings.json"));
+    let secret = pairings.create(Role::Viewer, Duration::from_secs(60))?;
+    // e.g. the config could not be updated, the secret is not used up
+    let res = pairings.redeem_with::<()>(&secret, |_| anyhow::bail!("config not saved"));
+    assert!(res.is_err());
+    assert_eq!(pairings.redeem(&secret)?, Role::Viewer);
+    Ok(())
+}
+
+#[test]
+fn test_long_ttl() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let pairings = Pairings::new(dir.path().join("pairings.json"));
+    assert!(pairings.create(Role::Viewer, Duration::MAX).is_err());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_mode() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("pairings.json");
+    std::fs::write(&path, "[]")?;
+    Pairings::new(&path).create(Role::Viewer, Duration::from_secs(60))?;
+    // only the daemon user can read the secrets
+    assert_eq!(
+        std::fs::metadata(&path)?.permissions().mode() & 0o777,
+        0o600
+    );
+    Ok(())
+}
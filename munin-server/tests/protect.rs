@@ -0,0 +1,34 @@
+use munin_proto::Error;
+use munin_server::ProtectedProcesses;
+
+#[test]
+fn test_builtin() {
+    let protected = ProtectedProcesses::new(&[]);
+    for pid in [1, std::process::id()] {
+        let res = protected.check(pid);
+        assert!(
+            matches!(res, Err(Error::ProtectedProcess { pid: p, .. }) if p == pid),
+            "{:?}",
+            res
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_configured() -> anyhow::Result<()> {
+    let mut child = std::process::Command::new("sleep").arg("10").spawn()?;
+    let pid = child.id();
+    assert!(ProtectedProcesses::new(&[]).check(pid).is_ok());
+    let res = ProtectedProcesses::new(&["Sleep".to_string()]).check(pid);
+    assert_eq!(
+        res,
+        Err(Error::ProtectedProcess {
+            pid,
+            name: "sleep".to_string()
+        })
+    );
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
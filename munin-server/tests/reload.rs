@@ -0,0 +1,41 @@
+use iroh_net::key::SecretKey;
+use munin_proto::{AuditOutcome, Error};
+use munin_server::{Config, Control};
+
+mod common;
+
+#[tokio::test]
+async fn test_reload() -> anyhow::Result<()> {
+    let daemon = common::start(Config::new(SecretKey::generate()))?;
+
+    // a new secret key would change the node id
+    Config {
+        secret_key: SecretKey::generate(),
+        ..daemon.config.clone()
+    }
+    .save()?;
+    daemon.control.send(Control::Reload)?;
+    let rejected = common::audit_entry(|entry| {
+        entry.request == "ReloadConfig" && entry.outcome != AuditOutcome::Ok
+    })
+    .await?;
+    let AuditOutcome::Error(Error::Failed(message)) = rejected.outcome else {
+        panic!("unexpected outcome {:?}", rejected.outcome);
+    };
+    assert!(message.contains("secret key"), "{}", message);
+
+    // other changes apply as soon as the file is written
+    Config {
+        name: "renamed".to_string(),
+        ..daemon.config.clone()
+    }
+    .save()?;
+    common::audit_entry(|entry| {
+        entry.request == "ReloadConfig" && entry.outcome == AuditOutcome::Ok
+    })
+    .await?;
+
+    daemon.control.send(Control::Shutdown)?;
+    daemon.task.await??;
+    Ok(())
+}
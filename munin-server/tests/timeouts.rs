@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use iroh_net::key::SecretKey;
+use munin_server::{Config, Control};
+
+mod common;
+
+#[tokio::test]
+async fn test_read_timeout() -> anyhow::Result<()> {
+    let mut config = Config::new(SecretKey::generate());
+    config.timeouts.read_secs = 1;
+    let daemon = common::start(config)?;
+    let client = common::client().await?;
+    let connection = client.connect(daemon.addr, munin_proto::ALPN).await?;
+
+    // the daemon closes a connection that never sends a request
+    let closed = tokio::time::timeout(Duration::from_secs(10), connection.closed()).await?;
+    assert!(closed.to_string().contains("timeout"), "{}", closed);
+
+    daemon.control.send(Control::Shutdown)?;
+    daemon.task.await??;
+    common::audit_entry(|entry| entry.request == "DaemonShutdown { aborted: 0 }").await?;
+    Ok(())
+}
@@ -19,9 +19,10 @@ mod munin_service {
     use crate::args::Subcommand;
     use clap::Parser;
     use iroh_base::key::NodeId;
+    use munin_proto::Grant;
     use munin_server::Config;
     use serde::{Deserialize, Serialize};
-    use std::{collections::BTreeSet, ffi::OsString, path::PathBuf, time::Duration};
+    use std::{collections::BTreeMap, ffi::OsString, path::PathBuf, time::Duration};
     use windows_service::{
         define_windows_service,
         service::{
@@ -230,8 +231,8 @@ mod munin_service {
                 let info: ServiceInfo = postcard::from_bytes(&data).unwrap();
                 println!("Public key: {}", info.pubkey);
                 println!("Allowed nodes");
-                for node in info.allowed_nodes {
-                    println!("  {}", node);
+                for (node, grant) in info.allowed_nodes {
+                    println!("  {} ({})", node, grant.role);
                 }
                 println!("Service config path: {:?}", info.path.join("config.toml"));
                 break;
@@ -259,14 +260,14 @@ mod munin_service {
 
                 // Handle stop
                 ServiceControl::Stop => {
-                    shutdown_tx.send(()).ok();
+                    shutdown_tx.send(munin_server::Control::Shutdown).ok();
                     ServiceControlHandlerResult::NoError
                 }
 
                 // treat the UserEvent as a stop request
                 ServiceControl::UserEvent(code) => {
                     if code.to_raw() == 130 {
-                        shutdown_tx.send(()).ok();
+                        shutdown_tx.send(munin_server::Control::Shutdown).ok();
                     }
                     ServiceControlHandlerResult::NoError
                 }
@@ -369,6 +370,6 @@ mod munin_service {
     struct ServiceInfo {
         path: PathBuf,
         pubkey: NodeId,
-        allowed_nodes: BTreeSet<NodeId>,
+        allowed_nodes: BTreeMap<NodeId, Grant>,
     }
 }